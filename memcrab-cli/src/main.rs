@@ -1,7 +1,8 @@
 use anyhow::anyhow;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use memcrab::{connections::Tcp, RawClient};
-use std::net::SocketAddr;
+use memcrab_server::{Eviction, Fsync, ServerCfg};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 #[derive(Parser)]
 #[command(author, version, about = "command line interface for memcrab (server and client)", long_about = None)]
@@ -22,6 +23,55 @@ enum Commands {
         // TODO: Take either SocketAddr for TCP or PathBuf for UNIX Socket
         #[arg(short, long, default_value = "127.0.0.1:9090")]
         address: SocketAddr,
+
+        /// Refuse requests with a bigger payload, in bytes
+        #[arg(long, default_value_t = memcrab_protocol::DEFAULT_MAX_PAYLOAD_LEN)]
+        max_payload_len: u64,
+
+        /// Serve at most this many connections at once, queue the rest
+        #[arg(long)]
+        max_connections: Option<usize>,
+
+        /// Close connections idle for this many seconds
+        #[arg(long)]
+        idle_timeout: Option<u64>,
+
+        /// Close connections that take longer than this many seconds to send a request, 0 for never
+        #[arg(long, default_value_t = 30)]
+        read_timeout: u64,
+
+        /// Which items a full cache evicts
+        #[arg(long, value_enum, default_value_t = EvictionArg::Lru)]
+        eviction: EvictionArg,
+
+        /// Load the cache from this file on start, save it there on shutdown and on `save`
+        #[arg(long)]
+        snapshot: Option<PathBuf>,
+
+        /// Also save a snapshot every this many seconds
+        #[arg(long, requires = "snapshot")]
+        snapshot_interval: Option<u64>,
+
+        /// Append every change to this file and replay it on start
+        #[arg(long)]
+        oplog: Option<PathBuf>,
+
+        /// How often the operation log is flushed to the disk
+        #[arg(long, value_enum, default_value_t = FsyncArg::EverySecond, requires = "oplog")]
+        oplog_fsync: FsyncArg,
+
+        /// Replicate the primary server at this address and refuse writes until `promote`
+        #[arg(long)]
+        replica_of: Option<SocketAddr>,
+
+        /// Answer replicate and promote requests from any client, only in a trusted network
+        #[arg(long)]
+        allow_replication: bool,
+
+        /// Serve Prometheus metrics over HTTP on this address, at /metrics
+        #[cfg(feature = "metrics")]
+        #[arg(long)]
+        metrics_address: Option<SocketAddr>,
     },
     Client {
         // #[arg(short = 'H', long, default_value = "127.0.0.1")]
@@ -36,6 +86,42 @@ enum Commands {
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum EvictionArg {
+    Lru,
+    Lfu,
+    Fifo,
+    TinyLfu,
+}
+
+impl From<EvictionArg> for Eviction {
+    fn from(arg: EvictionArg) -> Self {
+        match arg {
+            EvictionArg::Lru => Eviction::Lru,
+            EvictionArg::Lfu => Eviction::Lfu,
+            EvictionArg::Fifo => Eviction::Fifo,
+            EvictionArg::TinyLfu => Eviction::TinyLfu,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FsyncArg {
+    Always,
+    EverySecond,
+    Never,
+}
+
+impl From<FsyncArg> for Fsync {
+    fn from(arg: FsyncArg) -> Self {
+        match arg {
+            FsyncArg::Always => Fsync::Always,
+            FsyncArg::EverySecond => Fsync::EverySecond,
+            FsyncArg::Never => Fsync::Never,
+        }
+    }
+}
+
 fn tokenize_line(line: String) -> anyhow::Result<Vec<String>> {
     let mut tokens = Vec::new();
     let mut word = String::new();
@@ -82,40 +168,123 @@ async fn eval_line<C: memcrab::Rpc>(
 ) -> anyhow::Result<String> {
     let tokens = tokenize_line(line)?;
     match tokens.first().map(|s| s.as_ref()) {
-        Some("get") if tokens.len() == 2 => {
-            let value = client.get(&tokens[1]).await?;
-            match value {
-                Some(value) => {
-                    let string_value = String::from_utf8(value).unwrap();
-                    Ok(string_value)
-                }
+        Some("get") if tokens.len() == 2 => match client.get(tokens[1].as_str()).await? {
+            Some(value) => Ok(String::from_utf8_lossy(&value).into_owned()),
+            None => Ok(String::from("key not found")),
+        },
+        Some("set") if tokens.len() == 3 => {
+            client
+                .set(tokens[1].as_str(), tokens[2].to_owned().into())
+                .await?;
+            Ok(String::from("ok"))
+        }
+        Some("delete") if tokens.len() == 2 => match client.delete(tokens[1].as_str()).await? {
+            true => Ok(String::from("ok")),
+            false => Ok(String::from("key not found")),
+        },
+        Some("clear") if tokens.len() == 1 => {
+            client.clear().await?;
+            Ok(String::from("ok"))
+        }
+        Some("ping") if tokens.len() == 1 => {
+            client.ping().await?;
+            Ok(String::from("pong"))
+        }
+        Some("ttl") if tokens.len() == 2 => match client.ttl(tokens[1].as_str()).await? {
+            Some(Some(ttl)) => Ok(format!("{:?}", ttl)),
+            Some(None) => Ok(String::from("no expiration")),
+            None => Ok(String::from("key not found")),
+        },
+        Some("touch") if tokens.len() == 3 => {
+            let ttl = Duration::from_secs(tokens[2].parse()?);
+            match client.touch(tokens[1].as_str(), ttl).await? {
+                true => Ok(String::from("ok")),
+                false => Ok(String::from("key not found")),
+            }
+        }
+        Some("persist") if tokens.len() == 2 => match client.persist(tokens[1].as_str()).await? {
+            true => Ok(String::from("ok")),
+            false => Ok(String::from("key not found")),
+        },
+        Some("gat") if tokens.len() == 3 => {
+            let ttl = Duration::from_secs(tokens[2].parse()?);
+            match client.get_and_touch(tokens[1].as_str(), ttl).await? {
+                Some(value) => Ok(String::from_utf8_lossy(&value).into_owned()),
                 None => Ok(String::from("key not found")),
             }
         }
-        Some("set") if tokens.len() == 3 => {
-            client.set(&tokens[1], tokens[2].to_owned().into()).await?;
+        Some("save") if tokens.len() == 1 => {
+            client.save().await?;
+            Ok(String::from("ok"))
+        }
+        Some("promote") if tokens.len() == 1 => {
+            client.promote().await?;
             Ok(String::from("ok"))
         }
+        Some("stats") if tokens.len() == 1 => {
+            let stats = client.stats().await?;
+            let total = stats.total();
+            Ok(format!(
+                "uptime: {:?}\nconnections: {} open, {} total, {} refused\n\
+                 items: {}\nbytes: {} of {}\nhits: {}\nmisses: {}\nsets: {}\n\
+                 deletes: {}\nevictions: {}\nexpirations: {}",
+                stats.uptime,
+                stats.connections,
+                stats.total_connections,
+                stats.refused_connections,
+                total.items,
+                total.bytesize,
+                total.max_bytesize,
+                total.hits,
+                total.misses,
+                total.sets,
+                total.deletes,
+                total.evictions,
+                total.expirations,
+            ))
+        }
         _ => Err(anyhow!("syntax error")),
     }
 }
 
-async fn serve_memcrab(addr: SocketAddr) -> anyhow::Result<()> {
-    use memcrab_server::{serve, Cache};
+async fn serve_memcrab(addr: SocketAddr, eviction: Eviction, cfg: ServerCfg) -> anyhow::Result<()> {
+    use memcrab_server::{serve_with_shutdown, Cache};
     use tokio::net::TcpListener;
 
     let gb = 2_usize.pow(30);
     let cache = Cache::builder()
         .segments(10)
         .max_bytesize(gb)
+        .eviction(eviction)
         .build()
         .into();
 
     let listener = TcpListener::bind(addr).await.unwrap();
-    serve(listener, cache).await?;
+    serve_with_shutdown(listener, cache, cfg, shutdown_signal()).await?;
     Ok(())
 }
 
+// Completes on Ctrl-C (SIGINT), or on SIGTERM where it exists.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(target_family = "unix")]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = ctrl_c => {}
+                _ = terminate.recv() => {}
+            },
+            Err(_) => ctrl_c.await,
+        }
+    }
+    #[cfg(not(target_family = "unix"))]
+    ctrl_c.await;
+}
+
 async fn repl<C: memcrab::Rpc>(mut client: RawClient<C>) -> anyhow::Result<()> {
     let mut editor = rustyline::DefaultEditor::new()?;
 
@@ -144,8 +313,37 @@ async fn main() -> anyhow::Result<()> {
             let client = RawClient::<Tcp>::connect(address).await?;
             repl(client).await?;
         }
-        Commands::Server { address } => {
-            serve_memcrab(address).await?;
+        Commands::Server {
+            address,
+            max_payload_len,
+            max_connections,
+            idle_timeout,
+            read_timeout,
+            eviction,
+            snapshot,
+            snapshot_interval,
+            oplog,
+            oplog_fsync,
+            replica_of,
+            allow_replication,
+            #[cfg(feature = "metrics")]
+            metrics_address,
+        } => {
+            let eviction = eviction.into();
+            let cfg = ServerCfg::builder()
+                .max_payload_len(max_payload_len)
+                .max_connections(max_connections)
+                .idle_timeout(idle_timeout.map(Duration::from_secs))
+                .read_timeout((read_timeout > 0).then(|| Duration::from_secs(read_timeout)))
+                .snapshot_path(snapshot)
+                .snapshot_interval(snapshot_interval.map(Duration::from_secs))
+                .oplog_path(oplog)
+                .oplog_fsync(oplog_fsync.into())
+                .replica_of(replica_of)
+                .allow_replication(allow_replication);
+            #[cfg(feature = "metrics")]
+            let cfg = cfg.metrics_addr(metrics_address);
+            serve_memcrab(address, eviction, cfg.build()).await?;
         }
     }
 
@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use memcrab_protocol::fuzzing::{MsgKind, Parser, HEADER_SIZE};
+
+// The first byte is the message kind, the rest is the payload.
+fuzz_target!(|data: &[u8]| {
+    let Some((&kind, payload)) = data.split_first() else {
+        return;
+    };
+    let Ok(kind) = MsgKind::try_from(kind) else {
+        return;
+    };
+    let Ok(msg) = Parser.decode(kind, payload.to_vec()) else {
+        return;
+    };
+
+    // Whatever is accepted must survive a roundtrip.
+    let bytes = Parser.encode(0, msg.clone());
+    let (header, payload) = bytes.split_at(HEADER_SIZE);
+    let (kind, _, _) = Parser.decode_header(header).unwrap();
+    assert_eq!(Parser.decode(kind, payload.to_vec()).unwrap(), msg);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use memcrab_protocol::fuzzing::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Parser.decode_header(data);
+});
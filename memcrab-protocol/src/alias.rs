@@ -1,4 +1,7 @@
 pub type PayloadLen = u64;
+pub type RequestId = u32;
 
 pub type KeyLen = u64;
-pub type Expiration = u32;
+pub type ValueLen = u64;
+pub type ItemCount = u32;
+pub type Version = u64;
@@ -1,4 +1,4 @@
-use crate::Msg;
+use crate::{Msg, RequestId};
 use std::{array::TryFromSliceError, string::FromUtf8Error};
 use thiserror::Error;
 
@@ -22,8 +22,24 @@ pub enum ParseError {
     #[error("malformed string")]
     InvalidString(#[from] FromUtf8Error),
 
-    #[error("message is too big")]
-    TooBig,
+    #[error("header is shorter than expected")]
+    TruncatedHeader,
+
+    #[error("payload is shorter than expected")]
+    Truncated,
+
+    #[error("key length is out of range")]
+    KeyLenOutOfRange,
+
+    #[error("payload has trailing bytes")]
+    TrailingBytes,
+
+    #[error("invalid expiration")]
+    InvalidExpiration,
+
+    /// The payload is bigger than the receiver accepts. Nothing of it has been read.
+    #[error("payload of {len} bytes exceeds the limit of {max} bytes")]
+    TooBig { id: RequestId, len: u64, max: u64 },
 
     #[error("conversion from a slice to an array fails")]
     TryFromSlice(#[from] TryFromSliceError),
@@ -38,6 +38,25 @@ pub enum RequestKind {
     Set = 2,
     Delete = 3,
     Clear = 4,
+    GetMany = 5,
+    SetMany = 6,
+    DeleteMany = 7,
+    GetVersioned = 8,
+    Cas = 9,
+    Increment = 10,
+    Decrement = 11,
+    Add = 12,
+    Replace = 13,
+    Append = 14,
+    Prepend = 15,
+    Ttl = 16,
+    Touch = 17,
+    Persist = 18,
+    GetAndTouch = 19,
+    Stats = 20,
+    Save = 21,
+    Replicate = 22,
+    Promote = 23,
 }
 
 #[repr(u8)]
@@ -47,6 +66,17 @@ pub enum ResponseKind {
     Ok = 129,
     Value = 130,
     KeyNotFound = 131,
+    Values = 132,
+    Deleted = 133,
+    VersionedValue = 134,
+    VersionMismatch = 135,
+    Counter = 136,
+    NotCounter = 137,
+    NotStored = 138,
+    Ttl = 139,
+    Stats = 140,
+    Snapshot = 141,
+    Changes = 142,
 
     Error = 255,
 }
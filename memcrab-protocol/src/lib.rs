@@ -8,12 +8,22 @@ mod socket;
 use parser::Parser;
 use std::mem::size_of;
 
+pub use alias::{RequestId, Version};
 pub use err::{Error, ParseError};
-pub use msg::{Msg, Request, Response};
-pub use socket::Socket;
+pub use kind::RequestKind;
+pub use msg::{Expiration, Item, Msg, Request, Response, SegmentStats, Stats};
+pub use socket::{Socket, DEFAULT_MAX_PAYLOAD_LEN};
 pub use tokio::io::{AsyncRead, AsyncWrite};
 
-const HEADER_SIZE: usize = size_of::<u8>() + size_of::<u64>();
+const HEADER_SIZE: usize = size_of::<u8>() + size_of::<RequestId>() + size_of::<u64>();
+
+/// Internals for the fuzz targets, not a stable API.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::{kind::MsgKind, parser::Parser};
+
+    pub const HEADER_SIZE: usize = crate::HEADER_SIZE;
+}
 
 #[cfg(test)]
 mod tests {
@@ -21,6 +31,6 @@ mod tests {
 
     #[test]
     fn test_size() {
-        assert_eq!(HEADER_SIZE, 9);
+        assert_eq!(HEADER_SIZE, 13);
     }
 }
@@ -1,4 +1,5 @@
-use crate::alias::Expiration;
+use crate::{alias::Version, kind::RequestKind};
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Msg {
@@ -8,15 +9,156 @@ pub enum Msg {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Request {
-    Get(String),
+    Get(Vec<u8>),
     Set {
-        key: String,
+        key: Vec<u8>,
         value: Vec<u8>,
         expiration: Expiration,
     },
-    Delete(String),
+    Delete(Vec<u8>),
     Clear,
     Ping,
+    GetMany(Vec<Vec<u8>>),
+    /// Set every item, or none of them if any is too big.
+    SetMany(Vec<Item>),
+    DeleteMany(Vec<Vec<u8>>),
+    /// Get a value together with its current version.
+    GetVersioned(Vec<u8>),
+    /// Set a value only if its stored version is still `version`.
+    Cas {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expiration: Expiration,
+        version: Version,
+    },
+    /// Add `delta` to a counter. If the key is missing and `initial` is given,
+    /// the counter is created with the `initial` value and `expiration`.
+    Increment {
+        key: Vec<u8>,
+        delta: u64,
+        initial: Option<u64>,
+        expiration: Expiration,
+    },
+    /// Subtract `delta` from a counter, the result never goes below zero.
+    /// A missing key is handled as in [`Request::Increment`].
+    Decrement {
+        key: Vec<u8>,
+        delta: u64,
+        initial: Option<u64>,
+        expiration: Expiration,
+    },
+    /// Set a value only if the key is missing.
+    Add {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expiration: Expiration,
+    },
+    /// Set a value only if the key is present.
+    Replace {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expiration: Expiration,
+    },
+    /// Add bytes to the end of an existing value.
+    Append {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Add bytes to the beginning of an existing value.
+    Prepend {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Get the remaining time to live of a key.
+    Ttl(Vec<u8>),
+    /// Change the expiration of a key without rewriting its value.
+    Touch {
+        key: Vec<u8>,
+        expiration: Expiration,
+    },
+    /// Remove the expiration of a key.
+    Persist(Vec<u8>),
+    /// Get a value and change its expiration.
+    GetAndTouch {
+        key: Vec<u8>,
+        expiration: Expiration,
+    },
+    /// Get the metrics of the server.
+    Stats,
+    /// Write a snapshot of the cache to the file configured on the server.
+    Save,
+    /// Turn the connection into a replication stream: the server answers with
+    /// [`Response::Snapshot`] pieces of its state, then with [`Response::Changes`] for good.
+    /// The first batch of changes, which may be empty, ends the snapshot.
+    Replicate,
+    /// Make a replica stop replicating and accept changes.
+    Promote,
+}
+
+impl Request {
+    pub fn kind(&self) -> RequestKind {
+        match self {
+            Request::Get(_) => RequestKind::Get,
+            Request::Set { .. } => RequestKind::Set,
+            Request::Delete(_) => RequestKind::Delete,
+            Request::Clear => RequestKind::Clear,
+            Request::Ping => RequestKind::Ping,
+            Request::GetMany(_) => RequestKind::GetMany,
+            Request::SetMany(_) => RequestKind::SetMany,
+            Request::DeleteMany(_) => RequestKind::DeleteMany,
+            Request::GetVersioned(_) => RequestKind::GetVersioned,
+            Request::Cas { .. } => RequestKind::Cas,
+            Request::Increment { .. } => RequestKind::Increment,
+            Request::Decrement { .. } => RequestKind::Decrement,
+            Request::Add { .. } => RequestKind::Add,
+            Request::Replace { .. } => RequestKind::Replace,
+            Request::Append { .. } => RequestKind::Append,
+            Request::Prepend { .. } => RequestKind::Prepend,
+            Request::Ttl(_) => RequestKind::Ttl,
+            Request::Touch { .. } => RequestKind::Touch,
+            Request::Persist(_) => RequestKind::Persist,
+            Request::GetAndTouch { .. } => RequestKind::GetAndTouch,
+            Request::Stats => RequestKind::Stats,
+            Request::Save => RequestKind::Save,
+            Request::Replicate => RequestKind::Replicate,
+            Request::Promote => RequestKind::Promote,
+        }
+    }
+}
+
+/// A single entry of [`Request::SetMany`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub expiration: Expiration,
+}
+
+/// When a stored value expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Expiration {
+    /// The value is kept until it is removed or evicted.
+    #[default]
+    Never,
+    /// The value expires this long after the server handles the request.
+    After(Duration),
+    /// The value expires at this point in time, as seen by the server's clock.
+    At(SystemTime),
+}
+
+impl Expiration {
+    /// Time to live from now, `None` if the value never expires.
+    /// A point in time that has already passed gives zero.
+    pub fn ttl(&self) -> Option<Duration> {
+        match *self {
+            Expiration::Never => None,
+            Expiration::After(ttl) => Some(ttl),
+            Expiration::At(time) => Some(
+                time.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO),
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -26,4 +168,87 @@ pub enum Response {
     Error(String),
     KeyNotFound,
     Pong,
+    /// Per-key results of [`Request::GetMany`], in request order.
+    Values(Vec<Option<Vec<u8>>>),
+    /// Per-key results of [`Request::DeleteMany`], in request order.
+    Deleted(Vec<bool>),
+    VersionedValue {
+        value: Vec<u8>,
+        version: Version,
+    },
+    /// The key exists, but its version differs from the one in [`Request::Cas`].
+    VersionMismatch,
+    /// The new value of a counter.
+    Counter(u64),
+    /// The stored value is not a counter.
+    NotCounter,
+    /// The condition of [`Request::Add`] or [`Request::Replace`] failed,
+    /// or the key of [`Request::Append`] or [`Request::Prepend`] is missing.
+    NotStored,
+    /// Remaining time to live, `None` if the key never expires.
+    Ttl(Option<Duration>),
+    Stats(Stats),
+    /// A piece of the state of the server, answer to [`Request::Replicate`].
+    /// The pieces together are a snapshot in the format of the server.
+    Snapshot(Vec<u8>),
+    /// Records of changes that follow the snapshot, in the format of the server.
+    /// A record may be cut between two of them.
+    Changes(Vec<u8>),
+}
+
+/// Metrics of a server, answer to [`Request::Stats`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// Time since the server has started.
+    pub uptime: Duration,
+    /// Connections open right now.
+    pub connections: u64,
+    /// Connections accepted since the start.
+    pub total_connections: u64,
+    /// Connections closed at once, because too many were open.
+    pub refused_connections: u64,
+    /// One entry for every segment of the cache.
+    pub segments: Vec<SegmentStats>,
+}
+
+impl Stats {
+    /// The sum over all segments.
+    pub fn total(&self) -> SegmentStats {
+        self.segments
+            .iter()
+            .fold(SegmentStats::default(), |total, segment| SegmentStats {
+                hits: total.hits + segment.hits,
+                misses: total.misses + segment.misses,
+                sets: total.sets + segment.sets,
+                deletes: total.deletes + segment.deletes,
+                evictions: total.evictions + segment.evictions,
+                expirations: total.expirations + segment.expirations,
+                items: total.items + segment.items,
+                bytesize: total.bytesize + segment.bytesize,
+                max_bytesize: total.max_bytesize + segment.max_bytesize,
+            })
+    }
+}
+
+/// Metrics of one segment of the cache. Counters are totals since the server has started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SegmentStats {
+    /// Reads that found a value.
+    pub hits: u64,
+    /// Reads that found nothing.
+    pub misses: u64,
+    /// Values stored.
+    pub sets: u64,
+    /// Keys deleted by a request.
+    pub deletes: u64,
+    /// Values dropped to make room for others.
+    pub evictions: u64,
+    /// Values dropped because their time to live ran out.
+    pub expirations: u64,
+    /// Number of items right now.
+    pub items: u64,
+    /// Bytes used by keys and values right now.
+    pub bytesize: u64,
+    /// Bytes the segment may use.
+    pub max_bytesize: u64,
 }
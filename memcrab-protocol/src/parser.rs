@@ -1,10 +1,14 @@
 use crate::{
-    alias::{Expiration, KeyLen, PayloadLen},
+    alias::{ItemCount, KeyLen, PayloadLen, RequestId, ValueLen, Version},
     kind::{MsgKind, RequestKind, ResponseKind},
-    Msg, ParseError, Request, Response, HEADER_SIZE,
+    Expiration, Item, Msg, ParseError, Request, Response, SegmentStats, Stats, HEADER_SIZE,
 };
 use itertools::chain;
-use std::{mem::size_of, string::FromUtf8Error};
+use std::{
+    mem::size_of,
+    string::FromUtf8Error,
+    time::{Duration, UNIX_EPOCH},
+};
 
 #[derive(Clone, Debug)]
 pub struct Parser;
@@ -12,11 +16,19 @@ pub struct Parser;
 impl Parser {
     pub fn decode_header(
         &self,
-        chunk: &[u8; HEADER_SIZE],
-    ) -> Result<(MsgKind, PayloadLen), ParseError> {
-        let kind = MsgKind::try_from(chunk[0])?;
-        let payload_len = PayloadLen::from_be_bytes(chunk[1..].try_into()?);
-        Ok((kind, payload_len))
+        chunk: &[u8],
+    ) -> Result<(MsgKind, RequestId, PayloadLen), ParseError> {
+        if chunk.len() < HEADER_SIZE {
+            return Err(ParseError::TruncatedHeader);
+        }
+        let mut cursor = Cursor::new(chunk);
+        let [kind] = cursor.array()?;
+        let id = RequestId::from_be_bytes(cursor.array()?);
+        let payload_len = PayloadLen::from_be_bytes(cursor.array()?);
+        cursor.finish()?;
+
+        let kind = MsgKind::try_from(kind)?;
+        Ok((kind, id, payload_len))
     }
 }
 
@@ -32,25 +44,109 @@ impl Parser {
     fn decode_request(&self, kind: RequestKind, payload: Payload) -> Result<Request, ParseError> {
         use RequestKind as Kind;
         Ok(match kind {
-            Kind::Ping => Request::Ping,
-            Kind::Get => Request::Get(utf8(payload)?),
+            Kind::Ping => empty(&payload, Request::Ping)?,
+            Kind::Get => Request::Get(payload),
             Kind::Set => {
-                let (klen_bytes, tail) = payload.split_at(size_of::<KeyLen>());
-                let (exp_bytes, tail) = tail.split_at(size_of::<Expiration>());
-
-                let klen = KeyLen::from_be_bytes(klen_bytes.try_into()?);
-                let expiration = Expiration::from_be_bytes(exp_bytes.try_into()?);
-
-                let (key, value) = tail.split_at(klen as usize);
-                let key = utf8(key)?;
+                let (key, value, expiration) = decode_set(&payload)?;
                 Request::Set {
                     key,
-                    value: value.to_vec(),
+                    value,
+                    expiration,
+                }
+            }
+            Kind::Clear => empty(&payload, Request::Clear)?,
+            Kind::Delete => Request::Delete(payload),
+            Kind::GetMany => Request::GetMany(decode_keys(&payload)?),
+            Kind::SetMany => {
+                let mut cursor = Cursor::new(&payload);
+                let count = cursor.count()?;
+                let mut items = Vec::with_capacity(cursor.capacity_for(count));
+                for _ in 0..count {
+                    let klen = cursor.u64()?;
+                    let vlen = cursor.u64()?;
+                    let expiration = cursor.expiration()?;
+                    let key = cursor.key(klen)?;
+                    let value = cursor.bytes(vlen)?;
+                    items.push(Item {
+                        key,
+                        value,
+                        expiration,
+                    });
+                }
+                cursor.finish()?;
+                Request::SetMany(items)
+            }
+            Kind::DeleteMany => Request::DeleteMany(decode_keys(&payload)?),
+            Kind::GetVersioned => Request::GetVersioned(payload),
+            Kind::Cas => {
+                let mut cursor = Cursor::new(&payload);
+                let klen = cursor.u64()?;
+                let expiration = cursor.expiration()?;
+                let version = Version::from_be_bytes(cursor.array()?);
+                let key = cursor.key(klen)?;
+                Request::Cas {
+                    key,
+                    value: cursor.rest(),
+                    expiration,
+                    version,
+                }
+            }
+            Kind::Increment => {
+                let (key, delta, initial, expiration) = decode_counter(&payload)?;
+                Request::Increment {
+                    key,
+                    delta,
+                    initial,
                     expiration,
                 }
             }
-            Kind::Clear => Request::Clear,
-            Kind::Delete => Request::Delete(utf8(payload)?),
+            Kind::Decrement => {
+                let (key, delta, initial, expiration) = decode_counter(&payload)?;
+                Request::Decrement {
+                    key,
+                    delta,
+                    initial,
+                    expiration,
+                }
+            }
+            Kind::Add => {
+                let (key, value, expiration) = decode_set(&payload)?;
+                Request::Add {
+                    key,
+                    value,
+                    expiration,
+                }
+            }
+            Kind::Replace => {
+                let (key, value, expiration) = decode_set(&payload)?;
+                Request::Replace {
+                    key,
+                    value,
+                    expiration,
+                }
+            }
+            Kind::Append => {
+                let (key, value) = decode_key_value(&payload)?;
+                Request::Append { key, value }
+            }
+            Kind::Prepend => {
+                let (key, value) = decode_key_value(&payload)?;
+                Request::Prepend { key, value }
+            }
+            Kind::Ttl => Request::Ttl(payload),
+            Kind::Touch => {
+                let (key, expiration) = decode_touch(&payload)?;
+                Request::Touch { key, expiration }
+            }
+            Kind::Persist => Request::Persist(payload),
+            Kind::GetAndTouch => {
+                let (key, expiration) = decode_touch(&payload)?;
+                Request::GetAndTouch { key, expiration }
+            }
+            Kind::Stats => empty(&payload, Request::Stats)?,
+            Kind::Save => empty(&payload, Request::Save)?,
+            Kind::Replicate => empty(&payload, Request::Replicate)?,
+            Kind::Promote => empty(&payload, Request::Promote)?,
         })
     }
     fn decode_response(
@@ -60,18 +156,74 @@ impl Parser {
     ) -> Result<Response, ParseError> {
         use ResponseKind as Kind;
         Ok(match kind {
-            Kind::Ok => Response::Ok,
-            Kind::Pong => Response::Pong,
-            Kind::KeyNotFound => Response::KeyNotFound,
+            Kind::Ok => empty(&payload, Response::Ok)?,
+            Kind::Pong => empty(&payload, Response::Pong)?,
+            Kind::KeyNotFound => empty(&payload, Response::KeyNotFound)?,
             Kind::Value => Response::Value(payload),
             Kind::Error => Response::Error(utf8(payload)?),
+            Kind::Values => {
+                let mut cursor = Cursor::new(&payload);
+                let count = cursor.count()?;
+                let mut values = Vec::with_capacity(cursor.capacity_for(count));
+                for _ in 0..count {
+                    let value = match cursor.flag()? {
+                        true => {
+                            let vlen = cursor.u64()?;
+                            Some(cursor.bytes(vlen)?)
+                        }
+                        false => None,
+                    };
+                    values.push(value);
+                }
+                cursor.finish()?;
+                Response::Values(values)
+            }
+            Kind::Deleted => {
+                let mut cursor = Cursor::new(&payload);
+                let count = cursor.count()?;
+                let mut flags = Vec::with_capacity(cursor.capacity_for(count));
+                for _ in 0..count {
+                    flags.push(cursor.flag()?);
+                }
+                cursor.finish()?;
+                Response::Deleted(flags)
+            }
+            Kind::VersionedValue => {
+                let mut cursor = Cursor::new(&payload);
+                let version = Version::from_be_bytes(cursor.array()?);
+                Response::VersionedValue {
+                    value: cursor.rest(),
+                    version,
+                }
+            }
+            Kind::VersionMismatch => empty(&payload, Response::VersionMismatch)?,
+            Kind::Counter => {
+                let mut cursor = Cursor::new(&payload);
+                let counter = cursor.u64()?;
+                cursor.finish()?;
+                Response::Counter(counter)
+            }
+            Kind::NotCounter => empty(&payload, Response::NotCounter)?,
+            Kind::NotStored => empty(&payload, Response::NotStored)?,
+            Kind::Ttl => {
+                let mut cursor = Cursor::new(&payload);
+                let ttl = match cursor.flag()? {
+                    true => Some(Duration::from_millis(cursor.u64()?)),
+                    false => None,
+                };
+                cursor.finish()?;
+                Response::Ttl(ttl)
+            }
+            Kind::Stats => Response::Stats(decode_stats(&payload)?),
+            Kind::Snapshot => Response::Snapshot(payload),
+            Kind::Changes => Response::Changes(payload),
         })
     }
 }
 
 impl Parser {
-    // (kind + payload_len) + payload
-    pub fn encode(&self, msg: Msg) -> Vec<u8> {
+    // (kind + id + payload_len) + payload
+    pub fn encode(&self, id: RequestId, msg: Msg) -> Vec<u8> {
         let (kind, payload) = match msg {
             Msg::Request(req) => {
                 let (kind, payload) = self.encode_request(req);
@@ -83,27 +235,89 @@ impl Parser {
             }
         };
         let payload_len = (payload.len() as u64).to_be_bytes();
-        chain!([kind.into()], payload_len, payload).collect()
+        chain!([kind.into()], id.to_be_bytes(), payload_len, payload).collect()
     }
 
     fn encode_request(&self, req: Request) -> (RequestKind, Payload) {
         match req {
             Request::Ping => (RequestKind::Ping, vec![]),
             Request::Clear => (RequestKind::Clear, vec![]),
-            Request::Get(key) => (RequestKind::Get, key.into()),
-            Request::Delete(key) => (RequestKind::Delete, key.into()),
+            Request::Get(key) => (RequestKind::Get, key),
+            Request::Delete(key) => (RequestKind::Delete, key),
             Request::Set {
                 key,
                 value,
                 expiration,
+            } => (RequestKind::Set, encode_set(key, value, expiration)),
+            Request::GetMany(keys) => (RequestKind::GetMany, encode_keys(keys)),
+            Request::SetMany(items) => {
+                let mut payload = count(items.len());
+                for item in items {
+                    payload.extend((item.key.len() as KeyLen).to_be_bytes());
+                    payload.extend((item.value.len() as ValueLen).to_be_bytes());
+                    payload.extend(encode_expiration(item.expiration));
+                    payload.extend(item.key);
+                    payload.extend(item.value);
+                }
+                (RequestKind::SetMany, payload)
+            }
+            Request::DeleteMany(keys) => (RequestKind::DeleteMany, encode_keys(keys)),
+            Request::GetVersioned(key) => (RequestKind::GetVersioned, key),
+            Request::Cas {
+                key,
+                value,
+                expiration,
+                version,
             } => {
-                let key = Vec::from(key);
                 let klen = (key.len() as KeyLen).to_be_bytes();
-                let exp = expiration.to_be_bytes();
+                let exp = encode_expiration(expiration);
+                let version = version.to_be_bytes();
 
-                let payload = chain!(klen, exp, key, value).collect();
-                (RequestKind::Set, payload)
+                let payload = chain!(klen, exp, version, key, value).collect();
+                (RequestKind::Cas, payload)
+            }
+            Request::Increment {
+                key,
+                delta,
+                initial,
+                expiration,
+            } => (
+                RequestKind::Increment,
+                encode_counter(key, delta, initial, expiration),
+            ),
+            Request::Decrement {
+                key,
+                delta,
+                initial,
+                expiration,
+            } => (
+                RequestKind::Decrement,
+                encode_counter(key, delta, initial, expiration),
+            ),
+            Request::Add {
+                key,
+                value,
+                expiration,
+            } => (RequestKind::Add, encode_set(key, value, expiration)),
+            Request::Replace {
+                key,
+                value,
+                expiration,
+            } => (RequestKind::Replace, encode_set(key, value, expiration)),
+            Request::Append { key, value } => (RequestKind::Append, encode_key_value(key, value)),
+            Request::Prepend { key, value } => (RequestKind::Prepend, encode_key_value(key, value)),
+            Request::Ttl(key) => (RequestKind::Ttl, key),
+            Request::Touch { key, expiration } => {
+                (RequestKind::Touch, encode_touch(key, expiration))
+            }
+            Request::Persist(key) => (RequestKind::Persist, key),
+            Request::GetAndTouch { key, expiration } => {
+                (RequestKind::GetAndTouch, encode_touch(key, expiration))
             }
+            Request::Stats => (RequestKind::Stats, vec![]),
+            Request::Save => (RequestKind::Save, vec![]),
+            Request::Replicate => (RequestKind::Replicate, vec![]),
+            Request::Promote => (RequestKind::Promote, vec![]),
         }
     }
     fn encode_response(&self, resp: Response) -> (ResponseKind, Payload) {
@@ -113,10 +327,293 @@ impl Parser {
             Response::KeyNotFound => (ResponseKind::KeyNotFound, vec![]),
             Response::Value(val) => (ResponseKind::Value, val),
             Response::Error(emsg) => (ResponseKind::Error, emsg.into()),
+            Response::Values(values) => {
+                let mut payload = count(values.len());
+                for value in values {
+                    match value {
+                        Some(value) => {
+                            payload.push(1);
+                            payload.extend((value.len() as ValueLen).to_be_bytes());
+                            payload.extend(value);
+                        }
+                        None => payload.push(0),
+                    }
+                }
+                (ResponseKind::Values, payload)
+            }
+            Response::Deleted(flags) => {
+                let mut payload = count(flags.len());
+                payload.extend(flags.into_iter().map(u8::from));
+                (ResponseKind::Deleted, payload)
+            }
+            Response::VersionedValue { value, version } => {
+                let payload = chain!(version.to_be_bytes(), value).collect();
+                (ResponseKind::VersionedValue, payload)
+            }
+            Response::VersionMismatch => (ResponseKind::VersionMismatch, vec![]),
+            Response::Counter(value) => (ResponseKind::Counter, value.to_be_bytes().to_vec()),
+            Response::NotCounter => (ResponseKind::NotCounter, vec![]),
+            Response::NotStored => (ResponseKind::NotStored, vec![]),
+            Response::Ttl(ttl) => {
+                let payload = match ttl {
+                    Some(ttl) => {
+                        let millis = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+                        chain!([1], millis.to_be_bytes()).collect()
+                    }
+                    None => vec![0],
+                };
+                (ResponseKind::Ttl, payload)
+            }
+            Response::Stats(stats) => (ResponseKind::Stats, encode_stats(stats)),
+            Response::Snapshot(chunk) => (ResponseKind::Snapshot, chunk),
+            Response::Changes(records) => (ResponseKind::Changes, records),
         }
     }
 }
 
+// KeyLen, Expiration, Key, Value
+fn encode_set(key: Vec<u8>, value: Vec<u8>, expiration: Expiration) -> Payload {
+    let klen = (key.len() as KeyLen).to_be_bytes();
+    let exp = encode_expiration(expiration);
+    chain!(klen, exp, key, value).collect()
+}
+
+fn decode_set(payload: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Expiration), ParseError> {
+    let mut cursor = Cursor::new(payload);
+    let klen = cursor.u64()?;
+    let expiration = cursor.expiration()?;
+    let key = cursor.key(klen)?;
+    Ok((key, cursor.rest(), expiration))
+}
+
+// KeyLen, Key, Value
+fn encode_key_value(key: Vec<u8>, value: Vec<u8>) -> Payload {
+    let klen = (key.len() as KeyLen).to_be_bytes();
+    chain!(klen, key, value).collect()
+}
+
+fn decode_key_value(payload: &[u8]) -> Result<(Vec<u8>, Vec<u8>), ParseError> {
+    let mut cursor = Cursor::new(payload);
+    let klen = cursor.u64()?;
+    let key = cursor.key(klen)?;
+    Ok((key, cursor.rest()))
+}
+
+// Expiration, Key
+fn encode_touch(key: Vec<u8>, expiration: Expiration) -> Payload {
+    chain!(encode_expiration(expiration), key).collect()
+}
+
+fn decode_touch(payload: &[u8]) -> Result<(Vec<u8>, Expiration), ParseError> {
+    let mut cursor = Cursor::new(payload);
+    let expiration = cursor.expiration()?;
+    Ok((cursor.rest(), expiration))
+}
+
+// ItemCount, then (KeyLen, Key) for each key
+fn encode_keys(keys: Vec<Vec<u8>>) -> Payload {
+    let mut payload = count(keys.len());
+    for key in keys {
+        payload.extend((key.len() as KeyLen).to_be_bytes());
+        payload.extend(key);
+    }
+    payload
+}
+
+fn decode_keys(payload: &[u8]) -> Result<Vec<Vec<u8>>, ParseError> {
+    let mut cursor = Cursor::new(payload);
+    let count = cursor.count()?;
+    let mut keys = Vec::with_capacity(cursor.capacity_for(count));
+    for _ in 0..count {
+        let klen = cursor.u64()?;
+        keys.push(cursor.key(klen)?);
+    }
+    cursor.finish()?;
+    Ok(keys)
+}
+
+// Delta, (0 | 1), Initial, Expiration, Key
+fn encode_counter(
+    key: Vec<u8>,
+    delta: u64,
+    initial: Option<u64>,
+    expiration: Expiration,
+) -> Payload {
+    chain!(
+        delta.to_be_bytes(),
+        [u8::from(initial.is_some())],
+        initial.unwrap_or(0).to_be_bytes(),
+        encode_expiration(expiration),
+        key
+    )
+    .collect()
+}
+
+fn decode_counter(payload: &[u8]) -> Result<(Vec<u8>, u64, Option<u64>, Expiration), ParseError> {
+    let mut cursor = Cursor::new(payload);
+    let delta = cursor.u64()?;
+    let has_initial = cursor.flag()?;
+    let initial = cursor.u64()?;
+    let expiration = cursor.expiration()?;
+    let initial = has_initial.then_some(initial);
+    Ok((cursor.rest(), delta, initial, expiration))
+}
+
+// u64 (uptime in milliseconds), u64 (connections), u64 (total connections),
+// u64 (refused connections), ItemCount, then 9 u64 for each segment
+fn encode_stats(stats: Stats) -> Payload {
+    let uptime = u64::try_from(stats.uptime.as_millis()).unwrap_or(u64::MAX);
+    let mut payload: Payload = chain!(
+        uptime.to_be_bytes(),
+        stats.connections.to_be_bytes(),
+        stats.total_connections.to_be_bytes(),
+        stats.refused_connections.to_be_bytes(),
+        count(stats.segments.len())
+    )
+    .collect();
+    for segment in stats.segments {
+        let fields = [
+            segment.hits,
+            segment.misses,
+            segment.sets,
+            segment.deletes,
+            segment.evictions,
+            segment.expirations,
+            segment.items,
+            segment.bytesize,
+            segment.max_bytesize,
+        ];
+        payload.extend(fields.into_iter().flat_map(u64::to_be_bytes));
+    }
+    payload
+}
+
+fn decode_stats(payload: &[u8]) -> Result<Stats, ParseError> {
+    let mut cursor = Cursor::new(payload);
+    let uptime = Duration::from_millis(cursor.u64()?);
+    let connections = cursor.u64()?;
+    let total_connections = cursor.u64()?;
+    let refused_connections = cursor.u64()?;
+    let count = cursor.count()?;
+    let mut segments = Vec::with_capacity(cursor.capacity_for(count));
+    for _ in 0..count {
+        segments.push(SegmentStats {
+            hits: cursor.u64()?,
+            misses: cursor.u64()?,
+            sets: cursor.u64()?,
+            deletes: cursor.u64()?,
+            evictions: cursor.u64()?,
+            expirations: cursor.u64()?,
+            items: cursor.u64()?,
+            bytesize: cursor.u64()?,
+            max_bytesize: cursor.u64()?,
+        });
+    }
+    cursor.finish()?;
+    Ok(Stats {
+        uptime,
+        connections,
+        total_connections,
+        refused_connections,
+        segments,
+    })
+}
+
+// (0 = never | 1 = after | 2 = at), Milliseconds
+// "after" counts from the moment the request is handled, "at" is a Unix time.
+fn encode_expiration(expiration: Expiration) -> [u8; size_of::<u8>() + size_of::<u64>()] {
+    let (tag, time) = match expiration {
+        Expiration::Never => (0, Duration::ZERO),
+        Expiration::After(ttl) => (1, ttl),
+        // A time before the epoch has passed already, the epoch itself will do.
+        Expiration::At(time) => (2, time.duration_since(UNIX_EPOCH).unwrap_or_default()),
+    };
+    let millis = u64::try_from(time.as_millis()).unwrap_or(u64::MAX);
+    let mut bytes = [0; size_of::<u8>() + size_of::<u64>()];
+    bytes[0] = tag;
+    bytes[1..].copy_from_slice(&millis.to_be_bytes());
+    bytes
+}
+
+// For messages that consist of their kind only.
+fn empty<T>(payload: &[u8], msg: T) -> Result<T, ParseError> {
+    Cursor::new(payload).finish()?;
+    Ok(msg)
+}
+
+fn count(len: usize) -> Payload {
+    (len as ItemCount).to_be_bytes().to_vec()
+}
+
+/// Reads primitives from the front of a payload without ever reading past its end.
+struct Cursor<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(payload: &'a [u8]) -> Self {
+        Self { rest: payload }
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ParseError> {
+        if n > self.rest.len() {
+            return Err(ParseError::Truncated);
+        }
+        let (head, tail) = self.rest.split_at(n);
+        self.rest = tail;
+        Ok(head)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ParseError> {
+        Ok(self.take(N)?.try_into()?)
+    }
+    fn flag(&mut self) -> Result<bool, ParseError> {
+        let [byte] = self.array()?;
+        Ok(byte != 0)
+    }
+    fn u64(&mut self) -> Result<u64, ParseError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+    fn count(&mut self) -> Result<ItemCount, ParseError> {
+        Ok(ItemCount::from_be_bytes(self.array()?))
+    }
+    fn expiration(&mut self) -> Result<Expiration, ParseError> {
+        let [tag] = self.array()?;
+        let time = Duration::from_millis(self.u64()?);
+        Ok(match tag {
+            0 => Expiration::Never,
+            1 => Expiration::After(time),
+            2 => Expiration::At(
+                UNIX_EPOCH
+                    .checked_add(time)
+                    .ok_or(ParseError::InvalidExpiration)?,
+            ),
+            _ => return Err(ParseError::InvalidExpiration),
+        })
+    }
+    fn bytes(&mut self, len: u64) -> Result<Vec<u8>, ParseError> {
+        let len = usize::try_from(len).map_err(|_| ParseError::Truncated)?;
+        Ok(self.take(len)?.to_vec())
+    }
+    // A key length that points past the end of the payload is a lie, not a short read.
+    fn key(&mut self, klen: u64) -> Result<Vec<u8>, ParseError> {
+        self.bytes(klen).map_err(|_| ParseError::KeyLenOutOfRange)
+    }
+    fn finish(&self) -> Result<(), ParseError> {
+        match self.rest.is_empty() {
+            true => Ok(()),
+            false => Err(ParseError::TrailingBytes),
+        }
+    }
+    fn rest(&mut self) -> Vec<u8> {
+        let rest = self.rest.to_vec();
+        self.rest = &[];
+        rest
+    }
+    // Never trust a count from the wire with an allocation: every item takes at least one byte.
+    fn capacity_for(&self, count: ItemCount) -> usize {
+        (count as usize).min(self.rest.len())
+    }
+}
+
 fn utf8(bytes: impl Into<Vec<u8>>) -> Result<String, FromUtf8Error> {
     String::from_utf8(bytes.into())
 }
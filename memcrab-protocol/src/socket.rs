@@ -1,4 +1,4 @@
-use crate::{err::Error, Msg, Parser, HEADER_SIZE};
+use crate::{err::Error, Msg, ParseError, Parser, RequestId, HEADER_SIZE};
 use std::io;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -8,15 +8,32 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 pub struct Socket<S> {
     stream: S,
     parser: Parser,
+    max_payload_len: u64,
 }
 
+/// Payloads above this size are refused by [`Socket::new`], 64 MiB.
+pub const DEFAULT_MAX_PAYLOAD_LEN: u64 = 64 * 1024 * 1024;
+
 impl<S> Socket<S> {
     pub fn new(stream: S) -> Self {
         Self {
             stream,
             parser: Parser,
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN,
         }
     }
+    /// Refuse to receive messages with a payload bigger than `max_payload_len` bytes.
+    pub fn with_max_payload_len(mut self, max_payload_len: u64) -> Self {
+        self.max_payload_len = max_payload_len;
+        self
+    }
+    pub fn max_payload_len(&self) -> u64 {
+        self.max_payload_len
+    }
+    /// The wrapped stream. Reading from it directly breaks the framing of messages.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
 }
 
 impl<S> Socket<S>
@@ -25,10 +42,20 @@ where
 {
     /// Encode a message and write it to the socket.
     pub async fn send(&mut self, msg: Msg) -> Result<(), Error> {
-        let bytes = self.parser.encode(msg);
+        self.send_with_id(0, msg).await
+    }
+    /// Encode a message with the given request id and write it to the socket.
+    /// The server echoes the id of a request back in its response.
+    pub async fn send_with_id(&mut self, id: RequestId, msg: Msg) -> Result<(), Error> {
+        let bytes = self.parser.encode(id, msg);
         self.stream.write_all(&bytes).await?;
         Ok(())
     }
+    /// Shut down the write side of the stream.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        self.stream.shutdown().await?;
+        Ok(())
+    }
 }
 
 impl<S> Socket<S>
@@ -37,17 +64,33 @@ where
 {
     /// Wait for a complete message from socket and parse it.
     pub async fn recv(&mut self) -> Result<Msg, Error> {
+        let (_, msg) = self.recv_with_id().await?;
+        Ok(msg)
+    }
+    /// Wait for a complete message from socket and parse it together with its request id.
+    pub async fn recv_with_id(&mut self) -> Result<(RequestId, Msg), Error> {
         let mut header = [0; HEADER_SIZE];
         self.stream.read_exact(&mut header).await?;
-        let (kind, payload_len) = self.parser.decode_header(&header)?;
+        let (kind, id, payload_len) = self.parser.decode_header(&header)?;
+
+        // Check the length from the header before allocating anything for the payload.
+        let too_big = || ParseError::TooBig {
+            id,
+            len: payload_len,
+            max: self.max_payload_len,
+        };
+        if payload_len > self.max_payload_len {
+            return Err(too_big().into());
+        }
+        let payload_len = usize::try_from(payload_len).map_err(|_| too_big())?;
 
         let payload = if payload_len > 0 {
-            read_chunk_exact(&mut self.stream, payload_len as usize).await?
+            read_chunk_exact(&mut self.stream, payload_len).await?
         } else {
             vec![]
         };
         let msg = self.parser.decode(kind, payload)?;
-        Ok(msg)
+        Ok((id, msg))
     }
 }
 
@@ -67,9 +110,10 @@ mod test {
     use super::*;
     use crate::{
         kind::{MsgKind, RequestKind, ResponseKind},
-        Request, Response,
+        Expiration, Item, ParseError, Request, Response, SegmentStats, Stats,
     };
     use itertools::chain;
+    use std::time::{Duration, UNIX_EPOCH};
     use tokio_test::io::Builder;
 
     #[allow(unused)]
@@ -85,18 +129,24 @@ mod test {
         let zero_u64_bytes = &0u64.to_be_bytes();
 
         let mut data = vec![MsgKind::Request(RequestKind::Ping).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(zero_u64_bytes);
         assert_parsed(data, Msg::Request(Request::Ping)).await;
 
         let mut data = vec![MsgKind::Request(RequestKind::Get).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(2u64.to_be_bytes()); // rest of header
-        data.extend([97, 98]); // utf8 encoded key
-        assert_parsed(data, Msg::Request(Request::Get("ab".to_owned()))).await;
+        data.extend([97, 98]); // key
+        assert_parsed(data, Msg::Request(Request::Get(b"ab".to_vec()))).await;
 
         let mut data = vec![MsgKind::Request(RequestKind::Set).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         let klen = [0, 0, 0, 0, 0, 0, 0, 2];
-        let exp = [0, 0, 1, 0];
-        let key = [97, 98]; // utf8 encoded key
+        let exp: Vec<u8> = chain!([1], 256u64.to_be_bytes()).collect();
+        let key = [97, 98];
         let val = [1, 2, 3];
         let payload: Vec<u8> = chain!(klen, exp, key, val).collect();
         let payload_len = (payload.len() as u64).to_be_bytes();
@@ -106,42 +156,482 @@ mod test {
         assert_parsed(
             data,
             Msg::Request(Request::Set {
-                key: "ab".to_owned(),
+                key: b"ab".to_vec(),
                 value: vec![1, 2, 3],
-                expiration: 256,
+                expiration: Expiration::After(Duration::from_millis(256)),
             }),
         )
         .await;
 
         let mut data = vec![MsgKind::Request(RequestKind::Delete).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(2u64.to_be_bytes()); // rest of header
-        data.extend(vec![97, 98]); // utf8 encoded key
-        assert_parsed(data, Msg::Request(Request::Delete("ab".to_owned()))).await;
+        data.extend(vec![97, 98]); // key
+        assert_parsed(data, Msg::Request(Request::Delete(b"ab".to_vec()))).await;
 
         let mut data = vec![MsgKind::Request(RequestKind::Clear).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(zero_u64_bytes);
         assert_parsed(data, Msg::Request(Request::Clear)).await;
 
         let mut data = vec![MsgKind::Response(ResponseKind::Pong).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(zero_u64_bytes);
         assert_parsed(data, Msg::Response(Response::Pong)).await;
 
         let mut data = vec![MsgKind::Response(ResponseKind::Ok).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(zero_u64_bytes);
         assert_parsed(data, Msg::Response(Response::Ok)).await;
 
         let mut data = vec![MsgKind::Response(ResponseKind::Value).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(4u64.to_be_bytes());
         data.extend(vec![1, 2, 3, 4]); // msg
         assert_parsed(data, Msg::Response(Response::Value(vec![1, 2, 3, 4]))).await;
 
         let mut data = vec![MsgKind::Response(ResponseKind::KeyNotFound).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(zero_u64_bytes);
         assert_parsed(data, Msg::Response(Response::KeyNotFound)).await;
 
         let mut data = vec![MsgKind::Response(ResponseKind::Error).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
         data.extend(3u64.to_be_bytes()); // payload len
         data.extend(vec![101, 114, 114]); // msg
         assert_parsed(data, Msg::Response(Response::Error("err".to_owned()))).await;
     }
+
+    #[tokio::test]
+    async fn test_binary_keys() {
+        let key = vec![0, 159, 146, 150, 255]; // not valid utf8
+
+        let mut data = vec![MsgKind::Request(RequestKind::Get).into()];
+
+        data.extend(0u32.to_be_bytes()); // request id
+        data.extend((key.len() as u64).to_be_bytes());
+        data.extend(&key);
+        assert_parsed(data, Msg::Request(Request::Get(key.clone()))).await;
+
+        let set = Request::Set {
+            key: key.clone(),
+            value: vec![1, 2],
+            expiration: Expiration::Never,
+        };
+        let data = Parser.encode(0, Msg::Request(set.clone()));
+        assert_parsed(data, Msg::Request(set)).await;
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let mut data = vec![MsgKind::Request(RequestKind::GetMany).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        let payload: Vec<u8> = chain!(
+            2u32.to_be_bytes(),
+            1u64.to_be_bytes(),
+            [97],
+            2u64.to_be_bytes(),
+            [98, 99]
+        )
+        .collect();
+        data.extend((payload.len() as u64).to_be_bytes());
+        data.extend(payload);
+        let keys = vec![b"a".to_vec(), b"bc".to_vec()];
+        assert_parsed(data, Msg::Request(Request::GetMany(keys.clone()))).await;
+
+        let messages = [
+            Msg::Request(Request::DeleteMany(keys)),
+            Msg::Request(Request::SetMany(vec![
+                Item {
+                    key: b"a".to_vec(),
+                    value: vec![1, 2, 3],
+                    expiration: Expiration::Never,
+                },
+                Item {
+                    key: vec![],
+                    value: vec![],
+                    expiration: Expiration::After(Duration::from_secs(10)),
+                },
+            ])),
+            Msg::Response(Response::Values(vec![Some(vec![1]), None, Some(vec![])])),
+            Msg::Response(Response::Deleted(vec![true, false])),
+            Msg::Response(Response::Values(vec![])),
+        ];
+        for msg in messages {
+            let data = Parser.encode(0, msg.clone());
+            assert_parsed(data, msg).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cas() {
+        let mut data = vec![MsgKind::Request(RequestKind::Cas).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        let payload: Vec<u8> = chain!(
+            2u64.to_be_bytes(), // klen
+            [1],                // expiration after
+            5u64.to_be_bytes(), // milliseconds
+            7u64.to_be_bytes(), // version
+            [97, 98],           // key
+            [1, 2, 3]           // value
+        )
+        .collect();
+        data.extend((payload.len() as u64).to_be_bytes());
+        data.extend(payload);
+        let cas = Request::Cas {
+            key: b"ab".to_vec(),
+            value: vec![1, 2, 3],
+            expiration: Expiration::After(Duration::from_millis(5)),
+            version: 7,
+        };
+        assert_parsed(data, Msg::Request(cas)).await;
+
+        let messages = [
+            Msg::Request(Request::GetVersioned(b"ab".to_vec())),
+            Msg::Response(Response::VersionedValue {
+                value: vec![1, 2],
+                version: u64::MAX,
+            }),
+            Msg::Response(Response::VersionMismatch),
+        ];
+        for msg in messages {
+            let data = Parser.encode(0, msg.clone());
+            assert_parsed(data, msg).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counter() {
+        let mut data = vec![MsgKind::Request(RequestKind::Decrement).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        let payload: Vec<u8> = chain!(
+            3u64.to_be_bytes(),  // delta
+            [1],                 // has initial
+            10u64.to_be_bytes(), // initial
+            [2],                 // expiration at
+            60u64.to_be_bytes(), // unix milliseconds
+            [97]                 // key
+        )
+        .collect();
+        data.extend((payload.len() as u64).to_be_bytes());
+        data.extend(payload);
+        let decr = Request::Decrement {
+            key: b"a".to_vec(),
+            delta: 3,
+            initial: Some(10),
+            expiration: Expiration::At(UNIX_EPOCH + Duration::from_millis(60)),
+        };
+        assert_parsed(data, Msg::Request(decr)).await;
+
+        let messages = [
+            Msg::Request(Request::Increment {
+                key: b"a".to_vec(),
+                delta: u64::MAX,
+                initial: None,
+                expiration: Expiration::Never,
+            }),
+            Msg::Response(Response::Counter(42)),
+            Msg::Response(Response::NotCounter),
+        ];
+        for msg in messages {
+            let data = Parser.encode(0, msg.clone());
+            assert_parsed(data, msg).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conditional_set() {
+        let messages = [
+            Msg::Request(Request::Add {
+                key: b"lock".to_vec(),
+                value: vec![1],
+                expiration: Expiration::After(Duration::from_secs(30)),
+            }),
+            Msg::Request(Request::Replace {
+                key: b"lock".to_vec(),
+                value: vec![],
+                expiration: Expiration::Never,
+            }),
+            Msg::Response(Response::NotStored),
+        ];
+        for msg in messages {
+            let data = Parser.encode(0, msg.clone());
+            assert_parsed(data, msg).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_append() {
+        let mut data = vec![MsgKind::Request(RequestKind::Append).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        let payload: Vec<u8> = chain!(1u64.to_be_bytes(), [97], [1, 2]).collect();
+        data.extend((payload.len() as u64).to_be_bytes());
+        data.extend(payload);
+        let append = Request::Append {
+            key: b"a".to_vec(),
+            value: vec![1, 2],
+        };
+        assert_parsed(data, Msg::Request(append)).await;
+
+        let messages = [Msg::Request(Request::Prepend {
+            key: b"a".to_vec(),
+            value: vec![],
+        })];
+        for msg in messages {
+            let data = Parser.encode(0, msg.clone());
+            assert_parsed(data, msg).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let mut data = vec![MsgKind::Response(ResponseKind::Ttl).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        data.extend(9u64.to_be_bytes()); // payload len
+        data.push(1);
+        data.extend(1500u64.to_be_bytes());
+        let ttl = Response::Ttl(Some(Duration::from_millis(1500)));
+        assert_parsed(data, Msg::Response(ttl)).await;
+
+        let messages = [
+            Msg::Request(Request::Ttl(b"a".to_vec())),
+            Msg::Request(Request::Touch {
+                key: b"a".to_vec(),
+                expiration: Expiration::After(Duration::from_secs(60)),
+            }),
+            Msg::Request(Request::Persist(b"a".to_vec())),
+            Msg::Request(Request::GetAndTouch {
+                key: b"a".to_vec(),
+                expiration: Expiration::Never,
+            }),
+            Msg::Response(Response::Ttl(None)),
+        ];
+        for msg in messages {
+            let data = Parser.encode(0, msg.clone());
+            assert_parsed(data, msg).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let segment = SegmentStats {
+            hits: 1,
+            misses: 2,
+            sets: 3,
+            deletes: 4,
+            evictions: 5,
+            expirations: 6,
+            items: 7,
+            bytesize: 8,
+            max_bytesize: 9,
+        };
+        let stats = Stats {
+            uptime: Duration::from_millis(1500),
+            connections: 2,
+            total_connections: 10,
+            refused_connections: 1,
+            segments: vec![segment, SegmentStats::default()],
+        };
+        assert_eq!(stats.total(), segment);
+
+        let mut data = vec![MsgKind::Response(ResponseKind::Stats).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        data.extend((4 * 8 + 4 + 2 * 9 * 8u64).to_be_bytes()); // payload len
+        for n in [1500u64, 2, 10, 1] {
+            data.extend(n.to_be_bytes());
+        }
+        data.extend(2u32.to_be_bytes());
+        for n in (1..=9).chain([0; 9]) {
+            data.extend((n as u64).to_be_bytes());
+        }
+        assert_parsed(data, Msg::Response(Response::Stats(stats))).await;
+
+        let msg = Msg::Request(Request::Stats);
+        assert_parsed(Parser.encode(0, msg.clone()), msg).await;
+        let msg = Msg::Request(Request::Save);
+        assert_parsed(Parser.encode(0, msg.clone()), msg).await;
+    }
+
+    #[tokio::test]
+    async fn test_replication() {
+        for msg in [
+            Msg::Request(Request::Replicate),
+            Msg::Request(Request::Promote),
+            Msg::Response(Response::Snapshot(vec![1, 2, 3])),
+            Msg::Response(Response::Changes(vec![])),
+        ] {
+            assert_parsed(Parser.encode(0, msg.clone()), msg).await;
+        }
+
+        let mut data = vec![MsgKind::Response(ResponseKind::Changes).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        data.extend(2u64.to_be_bytes()); // payload len
+        data.extend([4, 0]);
+        assert_parsed(data, Msg::Response(Response::Changes(vec![4, 0]))).await;
+    }
+
+    #[test]
+    fn test_request_kind() {
+        let requests = [
+            Request::Ping,
+            Request::Get(b"a".to_vec()),
+            Request::SetMany(vec![]),
+            Request::Touch {
+                key: b"a".to_vec(),
+                expiration: Expiration::Never,
+            },
+            Request::Stats,
+            Request::Save,
+            Request::Replicate,
+            Request::Promote,
+        ];
+        for request in requests {
+            let kind = request.kind();
+            let data = Parser.encode(0, Msg::Request(request));
+            assert_eq!(data[0], u8::from(kind));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncated_batch() {
+        let mut data = vec![MsgKind::Request(RequestKind::GetMany).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        let payload: Vec<u8> = chain!(u32::MAX.to_be_bytes(), 1u64.to_be_bytes(), [97]).collect();
+        data.extend((payload.len() as u64).to_be_bytes());
+        data.extend(payload);
+
+        let mock = Builder::new().read(&data).build();
+        let err = Socket::new(mock).recv().await.unwrap_err();
+        assert!(matches!(err, Error::Parse(ParseError::Truncated)));
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let msg = Msg::Request(Request::Get(b"ab".to_vec()));
+        let data = Parser.encode(0xDEADBEEF, msg.clone());
+        assert_eq!(data[1..5], 0xDEADBEEFu32.to_be_bytes());
+
+        let mock = Builder::new().read(&data).write(&data).build();
+        let mut socket = Socket::new(mock);
+        let (id, parsed) = socket.recv_with_id().await.unwrap();
+        assert_eq!((id, &parsed), (0xDEADBEEF, &msg));
+        socket.send_with_id(id, parsed).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expiration() {
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        for expiration in [
+            Expiration::Never,
+            Expiration::After(Duration::from_millis(1)),
+            Expiration::At(at),
+        ] {
+            let touch = Msg::Request(Request::Touch {
+                key: b"a".to_vec(),
+                expiration,
+            });
+            let data = Parser.encode(0, touch.clone());
+            assert_parsed(data, touch).await;
+        }
+
+        let mut data = vec![MsgKind::Request(RequestKind::Touch).into()];
+        data.extend(0u32.to_be_bytes()); // request id
+        let payload: Vec<u8> = chain!([3], 0u64.to_be_bytes(), [97]).collect();
+        data.extend((payload.len() as u64).to_be_bytes());
+        data.extend(payload);
+
+        let mock = Builder::new().read(&data).build();
+        let err = Socket::new(mock).recv().await.unwrap_err();
+        assert!(matches!(err, Error::Parse(ParseError::InvalidExpiration)));
+    }
+
+    #[tokio::test]
+    async fn test_too_big() {
+        let mut data = vec![MsgKind::Request(RequestKind::Get).into()];
+        data.extend(7u32.to_be_bytes()); // request id
+        data.extend(u64::MAX.to_be_bytes()); // payload len, no payload follows
+
+        let mock = Builder::new().read(&data).build();
+        let err = Socket::new(mock).recv().await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Parse(ParseError::TooBig {
+                id: 7,
+                len: u64::MAX,
+                max: DEFAULT_MAX_PAYLOAD_LEN
+            })
+        ));
+
+        let data = Parser.encode(0, Msg::Request(Request::Get(b"abc".to_vec())));
+        let mock = Builder::new().read(&data[..HEADER_SIZE]).build();
+        let mut socket = Socket::new(mock).with_max_payload_len(2);
+        assert_eq!(socket.max_payload_len(), 2);
+        let err = socket.recv().await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Parse(ParseError::TooBig { len: 3, .. })
+        ));
+
+        let mock = Builder::new().read(&data).build();
+        let mut socket = Socket::new(mock).with_max_payload_len(3);
+        assert!(socket.recv().await.is_ok());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let header = Parser.encode(0, Msg::Request(Request::Ping));
+        assert!(Parser.decode_header(&header).is_ok());
+        let err = Parser
+            .decode_header(&header[..HEADER_SIZE - 1])
+            .unwrap_err();
+        assert!(matches!(err, ParseError::TruncatedHeader));
+        let err = Parser
+            .decode_header(&[header.as_slice(), &[0]].concat())
+            .unwrap_err();
+        assert!(matches!(err, ParseError::TrailingBytes));
+        let err = Parser.decode_header(&[200; HEADER_SIZE]).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownMsgKind));
+
+        let decode = |kind: MsgKind, payload: Vec<u8>| Parser.decode(kind, payload).unwrap_err();
+        let set = MsgKind::Request(RequestKind::Set);
+        let exp = [0; 9];
+
+        let err = decode(set, chain!(2u64.to_be_bytes(), exp, [97]).collect());
+        assert!(matches!(err, ParseError::KeyLenOutOfRange));
+        let err = decode(set, chain!(u64::MAX.to_be_bytes(), exp).collect());
+        assert!(matches!(err, ParseError::KeyLenOutOfRange));
+        for len in [0, 7, 8, 16] {
+            let payload: Vec<u8> = chain!(2u64.to_be_bytes(), exp).take(len).collect();
+            assert!(matches!(decode(set, payload), ParseError::Truncated));
+        }
+
+        let get_many = MsgKind::Request(RequestKind::GetMany);
+        let payload = chain!(1u32.to_be_bytes(), 1u64.to_be_bytes(), [97, 98]).collect();
+        assert!(matches!(
+            decode(get_many, payload),
+            ParseError::TrailingBytes
+        ));
+
+        let trailing = [
+            (MsgKind::Request(RequestKind::Ping), vec![0]),
+            (MsgKind::Request(RequestKind::Clear), vec![0]),
+            (MsgKind::Response(ResponseKind::Ok), vec![0]),
+            (MsgKind::Response(ResponseKind::Counter), vec![0; 9]),
+            (MsgKind::Response(ResponseKind::Ttl), vec![0, 0]),
+            (
+                MsgKind::Response(ResponseKind::Deleted),
+                vec![0, 0, 0, 0, 1],
+            ),
+        ];
+        for (kind, payload) in trailing {
+            assert!(matches!(decode(kind, payload), ParseError::TrailingBytes));
+        }
+    }
 }
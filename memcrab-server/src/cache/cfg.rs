@@ -1,9 +1,12 @@
-use std::sync::Mutex;
+use std::{sync::Mutex, time::Duration};
 use typed_builder::TypedBuilder;
 
 use crate::cache::map::Map;
 
-use super::{MemLru, Value};
+use super::{policy::Eviction, MemLru, Value};
+
+// Expected bytes of an item, to size the structures of a policy when there is no `max_len`.
+const TYPICAL_ITEM_SIZE: usize = 64;
 
 #[derive(TypedBuilder)]
 pub struct CacheCfg {
@@ -11,23 +14,28 @@ pub struct CacheCfg {
     #[builder(default=None, setter(strip_option))]
     max_len: Option<usize>,
     max_bytesize: usize,
+    /// Which items are evicted when a segment is full.
+    #[builder(default)]
+    eviction: Eviction,
+    /// How often a server removes expired items in the background.
+    /// With `None` they are removed only when they are accessed or evicted.
+    #[builder(default = Some(Duration::from_secs(1)))]
+    sweep_interval: Option<Duration>,
 }
 
 impl CacheCfg {
-    pub(super) fn map(self) -> Map<String, Value> {
+    pub(super) fn sweep_interval(&self) -> Option<Duration> {
+        self.sweep_interval
+    }
+    pub(super) fn map(self) -> Map<Vec<u8>, Value> {
         assert!(self.segments > 0);
 
+        let eviction = self.eviction;
         let new_segment = |max_bytesize: usize, max_len: Option<usize>| {
-            Mutex::new(match max_len {
-                Some(max_len) => {
-                    if max_len == 0 {
-                        MemLru::with_max_bytesize(max_bytesize)
-                    } else {
-                        MemLru::with_max_bytesize_and_max_len(max_bytesize, max_len)
-                    }
-                }
-                None => MemLru::with_max_bytesize(max_bytesize),
-            })
+            let max_len = max_len.filter(|&max_len| max_len > 0);
+            let capacity = max_len.unwrap_or(max_bytesize / TYPICAL_ITEM_SIZE);
+            let policy = eviction.policy(capacity);
+            Mutex::new(MemLru::new(max_bytesize, max_len, policy))
         };
 
         let mut segments = Vec::with_capacity(self.segments);
@@ -1,8 +1,9 @@
-use super::memlru::{ByteSized, MemLru};
+use super::memlru::{ByteSized, Expiring, MemLru, TooBig, Versioned};
 use core::{borrow::Borrow, hash::Hash};
 use std::{
     collections::hash_map::RandomState,
-    sync::{Mutex, MutexGuard},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Instant,
 };
 
 type Segment<K, V> = Mutex<MemLru<K, V>>;
@@ -18,8 +19,8 @@ where
 
 impl<K, V> Map<K, V>
 where
-    K: Hash + Eq + ByteSized,
-    V: ByteSized + Clone,
+    K: Hash + Ord + Clone + ByteSized,
+    V: ByteSized + Expiring + Versioned + Clone,
 {
     pub fn from_segments(segments: Segments<K, V>) -> Self {
         Self {
@@ -27,7 +28,7 @@ where
             hasher: RandomState::default(),
         }
     }
-    pub fn set(&self, key: K, val: V) -> Option<V> {
+    pub fn set(&self, key: K, val: V) -> Result<Option<V>, TooBig> {
         let mut segment = self.lock_segment_for_key(&key);
         segment.set(key, val)
     }
@@ -50,10 +51,91 @@ where
     }
     pub fn clear(&self) {
         self.segments.iter().for_each(|seg| {
-            let mut seg = seg.lock().unwrap();
+            let mut seg = lock(seg);
             seg.clear();
         })
     }
+    /// Clears every segment and calls `f`, holding the locks of all segments meanwhile.
+    pub fn clear_and_then<T>(&self, f: impl FnOnce() -> T) -> T {
+        // Always locked in order, so that two of these don't wait for each other.
+        let mut segments: Vec<_> = self.segments.iter().map(lock).collect();
+        segments.iter_mut().for_each(|seg| seg.clear());
+        f()
+    }
+    /// Sets every item, locking each affected segment once.
+    /// Nothing is set if any item is too big for its segment.
+    pub fn set_many(&self, items: Vec<(K, V)>) -> Result<Vec<Option<V>>, TooBig> {
+        let mut groups: Vec<Vec<(usize, K, V)>> = self.segments.iter().map(|_| vec![]).collect();
+        let len = items.len();
+        for (i, (key, val)) in items.into_iter().enumerate() {
+            groups[self.determine_segment(&key)].push((i, key, val));
+        }
+        for (at, group) in groups.iter().enumerate() {
+            let max_bytesize = lock(&self.segments[at]).max_bytesize();
+            let too_big = |(_, key, val): &(usize, K, V)| MemLru::size_of(key, val) > max_bytesize;
+            if group.iter().any(too_big) {
+                return Err(TooBig);
+            }
+        }
+
+        let mut out: Vec<Option<V>> = (0..len).map(|_| None).collect();
+        for (at, group) in groups.into_iter().enumerate() {
+            if group.is_empty() {
+                continue;
+            }
+            let mut segment = lock(&self.segments[at]);
+            for (i, key, val) in group {
+                out[i] = segment.set(key, val).expect("the item fits in the segment");
+            }
+        }
+        Ok(out)
+    }
+    /// Calls `f` for every key with the segment that owns it,
+    /// locking each affected segment once. Results keep the order of `keys`.
+    pub fn lock_many_and_then<Q, B, F, T>(&self, keys: &[B], mut f: F) -> Vec<T>
+    where
+        K: Borrow<Q>,
+        B: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&mut MemLru<K, V>, &Q) -> T,
+    {
+        let mut groups: Vec<Vec<usize>> = self.segments.iter().map(|_| vec![]).collect();
+        for (i, key) in keys.iter().enumerate() {
+            groups[self.determine_segment(&key.borrow())].push(i);
+        }
+
+        let mut out: Vec<Option<T>> = keys.iter().map(|_| None).collect();
+        for (at, group) in groups.into_iter().enumerate() {
+            if group.is_empty() {
+                continue;
+            }
+            let mut segment = lock(&self.segments[at]);
+            for i in group {
+                out[i] = Some(f(&mut segment, keys[i].borrow()));
+            }
+        }
+        out.into_iter()
+            .map(|t| t.expect("every key belongs to a segment"))
+            .collect()
+    }
+    /// Calls `f` once for every affected segment with the keys it owns, in the order of `keys`.
+    pub fn lock_groups_and_then<Q, B, F>(&self, keys: &[B], mut f: F)
+    where
+        K: Borrow<Q>,
+        B: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&mut MemLru<K, V>, Vec<&Q>),
+    {
+        let mut groups: Vec<Vec<&Q>> = self.segments.iter().map(|_| vec![]).collect();
+        for key in keys {
+            groups[self.determine_segment(&key.borrow())].push(key.borrow());
+        }
+        for (at, group) in groups.into_iter().enumerate() {
+            if !group.is_empty() {
+                f(&mut lock(&self.segments[at]), group);
+            }
+        }
+    }
     pub fn get_and_then<Q, F, T>(&self, key: &Q, f: F) -> T
     where
         K: Borrow<Q>,
@@ -65,9 +147,33 @@ where
         f(opt)
     }
 
-    fn lock_segment_for_key<T: Hash>(&self, key: &T) -> MutexGuard<MemLru<K, V>> {
+    /// Removes every item that has expired by now.
+    /// A segment stays locked for at most `batch` removals at a time, so requests can get in between.
+    pub fn remove_expired(&self, batch: usize) -> usize {
+        let now = Instant::now();
+        let mut removed = 0;
+        for segment in &self.segments {
+            loop {
+                let n = lock(segment).remove_expired(now, batch);
+                removed += n;
+                if n < batch {
+                    break;
+                }
+            }
+        }
+        removed
+    }
+    /// Calls `f` for every segment in order, locking one segment at a time.
+    pub fn segments_and_then<F, T>(&self, mut f: F) -> Vec<T>
+    where
+        F: FnMut(&mut MemLru<K, V>) -> T,
+    {
+        self.segments.iter().map(|seg| f(&mut lock(seg))).collect()
+    }
+
+    pub fn lock_segment_for_key<T: Hash>(&self, key: &T) -> MutexGuard<'_, MemLru<K, V>> {
         let at = self.determine_segment(key);
-        self.segments[at].lock().unwrap()
+        lock(&self.segments[at])
     }
     fn determine_segment<T: Hash>(&self, key: &T) -> usize {
         let hash = self.hash(key);
@@ -79,3 +185,8 @@ where
         self.hasher.hash_one(item)
     }
 }
+
+// A panic under the lock must not make the segment unusable for every later request.
+fn lock<K: Hash + Eq, V>(segment: &Segment<K, V>) -> MutexGuard<'_, MemLru<K, V>> {
+    segment.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,6 @@
+use std::time::Instant;
+
+pub trait Expiring {
+    /// When the item expires, `None` if it never does.
+    fn deadline(&self) -> Option<Instant>;
+}
@@ -1,18 +1,48 @@
 mod bytesized;
+mod expiring;
+mod versioned;
 
 pub use bytesized::ByteSized;
+pub use expiring::Expiring;
+pub use versioned::Versioned;
 
 use core::{borrow::Borrow, hash::Hash};
 use lru::LruCache;
+use std::{collections::BTreeSet, time::Instant};
+use thiserror::Error;
 
-#[derive(Debug)]
+use super::policy::EvictionPolicy;
+
+/// The item doesn't fit in a segment even when it's empty.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("item is too big")]
+pub struct TooBig;
+
+/// What happened in a segment since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub hits: u64,
+    pub misses: u64,
+    pub sets: u64,
+    pub deletes: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+/// A segment of the cache: items in the order they were used, limited in bytes and count.
 pub struct MemLru<K, V>
 where
     K: Hash + Eq,
 {
     inner: LruCache<K, V>,
+    policy: Box<dyn EvictionPolicy<K>>,
+    max_len: Option<usize>,
+    // Keys of the items that expire, in the order of their deadlines.
+    // Kept in sync with `inner`, so that expired items are found without a scan.
+    deadlines: BTreeSet<(Instant, K)>,
     max_bytesize: usize,
     bytesize: usize,
+    counters: Counters,
 }
 
 impl<K, V> ByteSized for MemLru<K, V>
@@ -26,33 +56,31 @@ where
 
 impl<K, V> MemLru<K, V>
 where
-    K: ByteSized + Hash + Eq,
-    V: ByteSized,
+    K: ByteSized + Hash + Ord + Clone,
+    V: ByteSized + Expiring + Versioned,
 {
-    pub fn with_max_bytesize(max_bytesize: usize) -> Self {
-        let inner = LruCache::unbounded();
-        Self::new(inner, max_bytesize)
-    }
-    pub fn with_max_bytesize_and_max_len(max_bytesize: usize, max_len: usize) -> Self {
-        use core::num::NonZeroUsize;
-        assert!(max_len > 0, "max_len should be > 0");
-        let max_len = NonZeroUsize::new(max_len).unwrap();
-        let inner = LruCache::new(max_len);
-        Self::new(inner, max_bytesize)
-    }
-    pub(crate) fn new(inner: LruCache<K, V>, max_bytesize: usize) -> Self {
+    pub fn new(
+        max_bytesize: usize,
+        max_len: Option<usize>,
+        policy: Box<dyn EvictionPolicy<K>>,
+    ) -> Self {
+        assert!(max_len != Some(0), "max_len should be > 0");
         Self {
-            inner,
+            inner: LruCache::unbounded(),
+            policy,
+            max_len,
+            deadlines: BTreeSet::new(),
             max_bytesize,
             bytesize: 0,
+            counters: Counters::default(),
         }
     }
     pub fn max_bytesize(&self) -> usize {
         self.max_bytesize
     }
     #[allow(unused)]
-    pub fn max_len(&self) -> usize {
-        self.inner.cap().into()
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
     }
     #[allow(unused)]
     pub fn len(&self) -> usize {
@@ -62,6 +90,25 @@ where
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
+    /// Sets and evictions are counted here, the other events by the owner of the segment.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+    pub fn counters_mut(&mut self) -> &mut Counters {
+        &mut self.counters
+    }
+    /// Looking doesn't count as a use.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.peek(key)
+    }
+    /// Items from the most to the least recently used, looking doesn't count as a use.
+    pub fn iter(&self) -> lru::Iter<'_, K, V> {
+        self.inner.iter()
+    }
     pub fn size_of(key: &K, val: &V) -> usize {
         key.bytesize() + val.bytesize()
     }
@@ -71,20 +118,79 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.inner.get(key)
+        let (k, val) = self.inner.get_key_value(key)?;
+        self.policy.on_access(k);
+        Some(val)
     }
-    pub fn set(&mut self, key: K, val: V) -> Option<V> {
+    /// Insert an item, evicting other items to make room for it.
+    /// Replacing a value counts as a use of the item, unless the old value was evicted
+    /// to make room. An item that is too big is refused, and the old value of its key
+    /// is dropped, so that it can't be mistaken for the new one.
+    pub fn set(&mut self, key: K, val: V) -> Result<Option<V>, TooBig> {
         let item_size = Self::size_of(&key, &val);
-        assert!(item_size <= self.max_bytesize());
+        if item_size > self.max_bytesize() {
+            self.pop(&key);
+            return Err(TooBig);
+        }
+
+        while self.cannot_replace(&key, item_size) && self.evict(None) {}
+        let deadline = val.deadline();
+        let result = match self.inner.get_mut(&key) {
+            Some(old) => {
+                let old = core::mem::replace(old, val);
+                self.policy.on_access(&key);
+                self.untrack(&key, &old);
+                self.track(&key, deadline, item_size);
+                Some(old)
+            }
+            None => {
+                self.push(key, val, item_size);
+                None
+            }
+        };
+        self.counters.sets += 1;
 
-        let result = self.pop(&key);
+        assert!(self.bytesize() <= self.max_bytesize());
+        Ok(result)
+    }
+    /// Modify a value in place, which counts as a use of the item, and as a set if `f` writes it.
+    /// The byte size is recalculated, so `f` may grow or shrink the value.
+    /// If the grown item doesn't fit, other items are evicted, never the item itself,
+    /// and if it cannot fit even in an empty cache, the item is dropped.
+    pub fn update<Q, F, T>(&mut self, key: &Q, f: F) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&mut V) -> T,
+    {
+        let (k, val) = self.inner.get_key_value_mut(key)?;
+        let (old_size, old_deadline, old_version) =
+            (Self::size_of(k, val), val.deadline(), val.version());
+        let result = f(val);
+        let (item_size, deadline) = (Self::size_of(k, val), val.deadline());
+        if val.version() != old_version {
+            self.counters.sets += 1;
+        }
+        let k = k.clone();
 
-        self.make_room_for(item_size);
-        self.inner.put(key, val);
+        self.policy.on_access(&k);
+        if deadline != old_deadline {
+            if let Some(old_deadline) = old_deadline {
+                self.deadlines.remove(&(old_deadline, k.clone()));
+            }
+            if let Some(deadline) = deadline {
+                self.deadlines.insert((deadline, k.clone()));
+            }
+        }
+        self.subtract_bytesize(old_size);
         self.add_bytesize(item_size);
 
-        assert!(self.bytesize() <= self.max_bytesize());
-        result
+        if item_size > self.max_bytesize() {
+            self.pop(key);
+            return Some(result);
+        }
+        while self.bytesize() > self.max_bytesize() && self.evict(Some(&k)) {}
+        Some(result)
     }
     pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
     where
@@ -95,43 +201,99 @@ where
     }
     pub fn clear(&mut self) {
         self.inner.clear();
+        self.policy.clear();
+        self.deadlines.clear();
         self.bytesize = 0;
     }
+    /// Remove at most `limit` items that have expired by `now`, the earliest first.
+    /// Returns how many were removed.
+    pub fn remove_expired(&mut self, now: Instant, limit: usize) -> usize {
+        let mut removed = 0;
+        while removed < limit {
+            match self.deadlines.first() {
+                Some((deadline, _)) if *deadline <= now => {}
+                _ => break,
+            }
+            let (_, key) = self.deadlines.pop_first().expect("deadline was found");
+            self.pop(&key);
+            self.counters.expirations += 1;
+            removed += 1;
+        }
+        removed
+    }
 
-    fn make_room_for(&mut self, item_size: usize) {
-        assert!(item_size <= self.max_bytesize());
-        while self.cannot_fit(item_size) {
-            self.pop_lru();
+    // Returns `false` if the segment is empty.
+    // Evicts an item other than `keep`.
+    fn evict(&mut self, keep: Option<&K>) -> bool {
+        let evicted = match self.policy.victim() {
+            Some(key) if Some(&key) != keep => self.pop(&key).is_some(),
+            _ => false,
+        };
+        // The least recently used item, also if the policy has lost track of its keys
+        // or would only evict `keep`.
+        let evicted = evicted
+            || match self.inner.peek_lru() {
+                Some((key, _)) if Some(key) != keep => self.pop_lru().is_some(),
+                _ => false,
+            };
+        if evicted {
+            self.counters.evictions += 1;
+        }
+        evicted
+    }
+    // The key must be absent.
+    fn push(&mut self, key: K, val: V, item_size: usize) {
+        self.track(&key, val.deadline(), item_size);
+        self.policy.on_insert(&key);
+        self.inner.push(key, val);
+    }
+    // Account for an item that has left `inner`.
+    fn forget(&mut self, key: &K, val: &V) {
+        self.policy.on_remove(key);
+        self.untrack(key, val);
+    }
+    fn track(&mut self, key: &K, deadline: Option<Instant>, item_size: usize) {
+        if let Some(deadline) = deadline {
+            self.deadlines.insert((deadline, key.clone()));
+        }
+        self.add_bytesize(item_size);
+    }
+    fn untrack(&mut self, key: &K, val: &V) {
+        if let Some(deadline) = val.deadline() {
+            self.deadlines.remove(&(deadline, key.clone()));
         }
+        self.subtract_bytesize(Self::size_of(key, val));
     }
     fn pop<Q>(&mut self, key: &Q) -> Option<V>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        match self.inner.pop_entry(key) {
-            Some((k, v)) => {
-                self.subtract_bytesize(Self::size_of(&k, &v));
-                Some(v)
-            }
-            None => None,
-        }
+        self.pop_entry(key).map(|(_, v)| v)
+    }
+    fn pop_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (k, v) = self.inner.pop_entry(key)?;
+        self.forget(&k, &v);
+        Some((k, v))
     }
     fn pop_lru(&mut self) -> Option<(K, V)> {
-        match self.inner.pop_lru() {
-            Some((k, v)) => {
-                self.subtract_bytesize(Self::size_of(&k, &v));
-                Some((k, v))
-            }
-            None => None,
-        }
+        let (k, v) = self.inner.pop_lru()?;
+        self.forget(&k, &v);
+        Some((k, v))
     }
 
-    fn can_fit(&self, item_size: usize) -> bool {
-        self.bytesize() + item_size <= self.max_bytesize()
-    }
-    fn cannot_fit(&self, item_size: usize) -> bool {
-        !self.can_fit(item_size)
+    // Whether the item fits in place of the current value of `key`, if there is one.
+    fn cannot_replace(&self, key: &K, item_size: usize) -> bool {
+        let (len, bytesize) = match self.inner.peek(key) {
+            Some(old) => (self.len(), self.bytesize() - Self::size_of(key, old)),
+            None => (self.len() + 1, self.bytesize()),
+        };
+        let len_fits = self.max_len.is_none_or(|max_len| len <= max_len);
+        !(len_fits && bytesize + item_size <= self.max_bytesize())
     }
     fn add_bytesize(&mut self, bytesize: usize) {
         self.bytesize += bytesize;
@@ -0,0 +1,4 @@
+pub trait Versioned {
+    /// Changes whenever the item is written.
+    fn version(&self) -> u64;
+}
@@ -1,94 +1,384 @@
 mod cfg;
 mod map;
 mod memlru;
+pub(crate) mod oplog;
+mod policy;
+mod snapshot;
+mod sweeper;
 mod value;
 
-use std::num::NonZeroU32;
+use std::{borrow::Borrow, future::Future, sync::Arc, time::Duration};
+use thiserror::Error;
 
 use map::Map;
-use memlru::{ByteSized, MemLru};
+use memlru::{ByteSized, Counters, MemLru, Versioned};
+
+pub use memlru::TooBig;
+pub use oplog::Fsync;
+pub use policy::Eviction;
 use value::Value;
 
 pub(crate) use cfg::CacheCfg;
 use cfg::CacheCfgBuilder;
 
 pub struct Cache {
-    inner: Map<String, Value>,
+    inner: Arc<Map<Vec<u8>, Value>>,
+    sweep_interval: Option<Duration>,
 }
 
 impl Cache {
-    pub fn new(inner: Map<String, Value>) -> Self {
-        Cache { inner }
+    pub fn new(inner: Map<Vec<u8>, Value>) -> Self {
+        Cache {
+            inner: Arc::new(inner),
+            sweep_interval: None,
+        }
     }
     pub fn builder() -> CacheCfgBuilder {
         CacheCfg::builder()
     }
+    /// Removes expired items in the background, while the server runs.
+    pub(crate) fn sweeper(&self) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let interval = self.sweep_interval?;
+        Some(sweeper::sweep(Arc::downgrade(&self.inner), interval))
+    }
 }
 
 impl From<CacheCfg> for Cache {
     fn from(cfg: CacheCfg) -> Self {
-        Self::new(cfg.map())
+        let sweep_interval = cfg.sweep_interval();
+        Self {
+            sweep_interval,
+            ..Self::new(cfg.map())
+        }
     }
 }
 
 impl Cache {
-    pub fn set(&self, key: String, value: Vec<u8>) {
+    pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), TooBig> {
         self._set(key, Value::new(value))
     }
-    pub fn set_with_expiration(&self, key: String, value: Vec<u8>, exp: NonZeroU32) {
-        self._set(key, Value::with_expiration(value, exp))
+    pub fn set_with_expiration(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), TooBig> {
+        self._set(key, Value::with_expiration(value, ttl))
     }
-    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        self._get(key)
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        read(&mut segment, key).map(|val| val.clone().into_vec())
     }
-    pub fn remove(&self, key: &str) -> Option<Vec<u8>> {
-        match self.inner.remove(key) {
-            Some(val) => {
-                if val.expired() {
-                    None
-                } else {
-                    Some(val.into_vec())
-                }
-            }
-            None => None,
-        }
+    pub fn remove(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        delete(&mut segment, key)
     }
     pub fn clear(&self) {
         self.inner.clear()
     }
+
+    /// Items without an expiration never expire.
+    /// If any item is too big, none of them is stored.
+    pub fn set_many(&self, items: Vec<(Vec<u8>, Vec<u8>, Option<Duration>)>) -> Result<(), TooBig> {
+        let items = items
+            .into_iter()
+            .map(|(key, value, exp)| (key, new_value(value, exp)))
+            .collect();
+        self.inner.set_many(items).map(drop)
+    }
+    pub fn get_with_version(&self, key: &[u8]) -> Option<(Vec<u8>, u64)> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        read(&mut segment, key).map(|val| (val.clone().into_vec(), val.version()))
+    }
+    /// Set the value only if the version of the stored value is still `version`.
+    pub fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        exp: Option<Duration>,
+        version: u64,
+    ) -> Result<CasOutcome, TooBig> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        Ok(match live(&mut segment, &key) {
+            None => CasOutcome::NotFound,
+            Some(val) if val.version() != version => CasOutcome::VersionMismatch,
+            Some(_) => {
+                segment.set(key, new_value(value, exp))?;
+                CasOutcome::Stored
+            }
+        })
+    }
+    /// Remaining time to live: `None` if the key is missing, `Some(None)` if it never expires.
+    pub fn ttl(&self, key: &[u8]) -> Option<Option<Duration>> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        live(&mut segment, key).map(Value::ttl)
+    }
+    /// Change the expiration of a key, `None` removes it. Returns whether the key exists.
+    pub fn touch(&self, key: &[u8], exp: Option<Duration>) -> bool {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        if live(&mut segment, key).is_none() {
+            return false;
+        }
+        segment.update(key, |val| val.set_expiration(exp)).is_some()
+    }
+    /// Remove the expiration of a key. Returns whether the key exists.
+    pub fn persist(&self, key: &[u8]) -> bool {
+        self.touch(key, None)
+    }
+    /// Get a value and change its expiration in one step.
+    pub fn get_and_touch(&self, key: &[u8], exp: Option<Duration>) -> Option<Vec<u8>> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        read(&mut segment, key)?;
+        segment.update(key, |val| {
+            val.set_expiration(exp);
+            val.clone().into_vec()
+        })
+    }
+    /// Set the value only if the key is missing. Returns whether it was stored.
+    pub fn add(&self, key: Vec<u8>, value: Vec<u8>, exp: Option<Duration>) -> Result<bool, TooBig> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        if live(&mut segment, &key).is_some() {
+            return Ok(false);
+        }
+        segment.set(key, new_value(value, exp))?;
+        Ok(true)
+    }
+    /// Set the value only if the key is present. Returns whether it was stored.
+    pub fn replace(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        exp: Option<Duration>,
+    ) -> Result<bool, TooBig> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        if live(&mut segment, &key).is_none() {
+            return Ok(false);
+        }
+        segment.set(key, new_value(value, exp))?;
+        Ok(true)
+    }
+    /// Add bytes to the end of an existing value, keeping its expiration.
+    pub fn append(&self, key: &[u8], bytes: &[u8]) -> Result<(), AppendError> {
+        self.extend(key, bytes, Value::append)
+    }
+    /// Add bytes to the beginning of an existing value, keeping its expiration.
+    pub fn prepend(&self, key: &[u8], bytes: &[u8]) -> Result<(), AppendError> {
+        self.extend(key, bytes, Value::prepend)
+    }
+    /// Add `delta` to the counter at `key`, wrapping around on overflow.
+    /// A missing counter is created from `init` (initial value and expiration), if given.
+    pub fn increment(
+        &self,
+        key: Vec<u8>,
+        delta: u64,
+        init: Option<(u64, Option<Duration>)>,
+    ) -> Result<u64, CounterError> {
+        self.update_counter(key, init, |n| n.wrapping_add(delta))
+    }
+    /// Subtract `delta` from the counter at `key`, stopping at zero.
+    /// A missing counter is created from `init` (initial value and expiration), if given.
+    pub fn decrement(
+        &self,
+        key: Vec<u8>,
+        delta: u64,
+        init: Option<(u64, Option<Duration>)>,
+    ) -> Result<u64, CounterError> {
+        self.update_counter(key, init, |n| n.saturating_sub(delta))
+    }
+    pub fn get_many<K: Borrow<[u8]>>(&self, keys: &[K]) -> Vec<Option<Vec<u8>>> {
+        self.inner.lock_many_and_then(keys, |segment, key| {
+            read(segment, key).map(|val| val.clone().into_vec())
+        })
+    }
+    pub fn remove_many<K: Borrow<[u8]>>(&self, keys: &[K]) -> Vec<Option<Vec<u8>>> {
+        self.inner.lock_many_and_then(keys, delete)
+    }
+    /// Metrics of every segment, in order.
+    pub fn stats(&self) -> Vec<SegmentStats> {
+        self.inner.segments_and_then(|segment| SegmentStats {
+            counters: segment.counters(),
+            items: segment.len(),
+            bytesize: segment.bytesize(),
+            max_bytesize: segment.max_bytesize(),
+        })
+    }
 }
 
 impl Cache {
-    fn _set(&self, key: String, value: Value) {
-        self.inner.set(key, value);
-    }
-    fn _get(&self, key: &str) -> Option<Vec<u8>> {
-        // Don't forget that the mutex is locked until the function returns.
-        // Accessing the same key inside will result in a deadlock.
-        let f = |opt: Option<&Value>| {
-            if let Some(val) = opt {
-                if val.expired() {
-                    LazyVal::Expired
-                } else {
-                    LazyVal::Val(val.clone().into_vec())
-                }
-            } else {
-                LazyVal::NotFound
-            }
-        };
-        match self.inner.get_and_then(key, f) {
-            LazyVal::Val(val) => Some(val),
-            LazyVal::Expired => {
-                self.inner.remove(key);
-                None
-            }
-            LazyVal::NotFound => None,
+    fn _set(&self, key: Vec<u8>, value: Value) -> Result<(), TooBig> {
+        self.inner.set(key, value).map(drop)
+    }
+}
+
+/// Metrics of one segment of a [`Cache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentStats {
+    counters: Counters,
+    items: usize,
+    bytesize: usize,
+    max_bytesize: usize,
+}
+
+impl SegmentStats {
+    /// Reads that found a value.
+    pub fn hits(&self) -> u64 {
+        self.counters.hits
+    }
+    /// Reads that found nothing.
+    pub fn misses(&self) -> u64 {
+        self.counters.misses
+    }
+    /// Values stored.
+    pub fn sets(&self) -> u64 {
+        self.counters.sets
+    }
+    /// Keys deleted on request.
+    pub fn deletes(&self) -> u64 {
+        self.counters.deletes
+    }
+    /// Values dropped to make room for others.
+    pub fn evictions(&self) -> u64 {
+        self.counters.evictions
+    }
+    /// Values dropped because their time to live ran out.
+    pub fn expirations(&self) -> u64 {
+        self.counters.expirations
+    }
+    pub fn items(&self) -> usize {
+        self.items
+    }
+    pub fn bytesize(&self) -> usize {
+        self.bytesize
+    }
+    pub fn max_bytesize(&self) -> usize {
+        self.max_bytesize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CasOutcome {
+    Stored,
+    VersionMismatch,
+    NotFound,
+}
+
+fn new_value(value: Vec<u8>, exp: Option<Duration>) -> Value {
+    match exp {
+        Some(exp) => Value::with_expiration(value, exp),
+        None => Value::new(value),
+    }
+}
+
+// Removes the value if it has expired, so that a segment never hands out a stale one.
+fn live<'a>(segment: &'a mut MemLru<Vec<u8>, Value>, key: &[u8]) -> Option<&'a Value> {
+    if segment.get(key)?.expired() {
+        segment.remove(key);
+        segment.counters_mut().expirations += 1;
+        return None;
+    }
+    segment.get(key)
+}
+
+// Like `live`, for requests that read the value: counts a hit or a miss.
+fn read<'a>(segment: &'a mut MemLru<Vec<u8>, Value>, key: &[u8]) -> Option<&'a Value> {
+    let found = live(segment, key).is_some();
+    let counters = segment.counters_mut();
+    match found {
+        true => counters.hits += 1,
+        false => counters.misses += 1,
+    }
+    segment.get(key)
+}
+
+fn delete(segment: &mut MemLru<Vec<u8>, Value>, key: &[u8]) -> Option<Vec<u8>> {
+    let val = segment.remove(key)?;
+    let counters = segment.counters_mut();
+    if val.expired() {
+        counters.expirations += 1;
+        None
+    } else {
+        counters.deletes += 1;
+        Some(val.into_vec())
+    }
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum AppendError {
+    #[error("key not found")]
+    NotFound,
+
+    #[error("value would be too big")]
+    TooBig,
+}
+
+impl Cache {
+    fn extend(
+        &self,
+        key: &[u8],
+        bytes: &[u8],
+        f: impl FnOnce(&mut Value, &[u8]),
+    ) -> Result<(), AppendError> {
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        let val = live(&mut segment, key).ok_or(AppendError::NotFound)?;
+        // Refuse to grow a value beyond the segment, instead of losing it.
+        let item_size = key.len() + val.as_slice().len() + bytes.len();
+        if item_size > segment.max_bytesize() {
+            return Err(AppendError::TooBig);
+        }
+        segment.update(key, |val| f(val, bytes));
+        // The byte size counts capacities, which may be over the lengths, so that
+        // `update` may still have dropped the grown item.
+        if segment.peek(key).is_none() {
+            return Err(AppendError::TooBig);
         }
+        Ok(())
     }
 }
 
-enum LazyVal {
-    Expired,
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum CounterError {
+    #[error("key not found")]
     NotFound,
-    Val(Vec<u8>),
+
+    #[error("value is not a counter")]
+    NotCounter,
+
+    #[error(transparent)]
+    TooBig(#[from] TooBig),
+}
+
+impl Cache {
+    fn update_counter(
+        &self,
+        key: Vec<u8>,
+        init: Option<(u64, Option<Duration>)>,
+        op: impl FnOnce(u64) -> u64,
+    ) -> Result<u64, CounterError> {
+        // The whole read-modify-write happens under the segment lock.
+        let mut segment = self.inner.lock_segment_for_key(&key);
+        if live(&mut segment, &key).is_none() {
+            let (initial, exp) = init.ok_or(CounterError::NotFound)?;
+            segment.set(key, new_value(encode_counter(initial), exp))?;
+            return Ok(initial);
+        }
+        let result = segment.update(key.as_slice(), |val| {
+            let n = decode_counter(val.as_slice()).ok_or(CounterError::NotCounter)?;
+            let n = op(n);
+            val.replace(encode_counter(n));
+            Ok(n)
+        });
+        result.expect("value is alive")
+    }
+}
+
+// Counters are stored in decimal, so that they are readable with a plain get.
+fn encode_counter(n: u64) -> Vec<u8> {
+    n.to_string().into_bytes()
+}
+
+fn decode_counter(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()?.parse().ok()
 }
@@ -0,0 +1,409 @@
+//! Operation log format, all numbers are big-endian:
+//!
+//! ```text
+//! snapshot (see `snapshot.rs`),
+//! (1, KeyLen, Key, ValueLen, Value
+//!  | 2, u64 (Unix time of the expiration in milliseconds), KeyLen, Key, ValueLen, Value
+//!  | 3, KeyLen, Key
+//!  | 4)*
+//! ```
+//!
+//! The snapshot is the state at the last compaction. Every record after it is the state
+//! of a key after a change (a value or a removal) or a clear, so a record that is replayed
+//! on top of a newer state does no harm.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use super::{
+    snapshot::{invalid_data, read_bytes, read_u64, unix_millis},
+    Cache, MemLru, Value,
+};
+
+const PUT: u8 = 1;
+const PUT_EXPIRING: u8 = 2;
+const REMOVE: u8 = 3;
+const CLEAR: u8 = 4;
+
+/// How often the operation log is flushed to the disk.
+///
+/// Records are handed to the operating system at once in any case,
+/// so a crash of the server loses nothing, only a crash of the machine does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fsync {
+    /// After every change, before it is answered.
+    /// Changes that are written together share a flush.
+    Always,
+    /// Once a second in the background, a crash loses at most the last second.
+    #[default]
+    EverySecond,
+    /// Whenever the operating system decides to.
+    Never,
+}
+
+/// What a request has changed.
+pub(crate) enum Change {
+    Keys(Vec<Vec<u8>>),
+    Clear,
+}
+
+/// Completes once appended records are written, and flushed with [`Fsync::Always`].
+pub(crate) type Written = oneshot::Receiver<io::Result<()>>;
+
+// Records for the writer, with where to report that they are written.
+type Append = (Arc<[u8]>, oneshot::Sender<io::Result<()>>);
+
+/// A log of every change to a cache, to restore it after a crash.
+/// Records are written by a thread of the log, in the order they are appended.
+pub(crate) struct OpLog {
+    path: PathBuf,
+    compaction_size: u64,
+    inner: Arc<Mutex<Inner>>,
+    writes: mpsc::Sender<Append>,
+}
+
+struct Inner {
+    file: Arc<File>,
+    len: u64,
+    // The length right after the last compaction.
+    compacted_len: u64,
+    // Written since the last fsync.
+    dirty: bool,
+    // Records written while a compaction is running, they go to the end of the compacted log.
+    rewrite: Option<Vec<u8>>,
+}
+
+impl OpLog {
+    /// Replay the log at `path` into `cache`, or start a new one from the state of `cache`.
+    /// An incomplete record at the end, e.g. after a crash in the middle of a write, is dropped.
+    /// The log is compacted once it's at least `compaction_size` bytes long
+    /// and twice as long as after the last compaction.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        fsync: Fsync,
+        compaction_size: u64,
+        cache: &Cache,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let (file, len) = match File::open(&path) {
+            Ok(file) => {
+                let started = Instant::now();
+                let (len, records) = replay(BufReader::new(file), cache)?;
+                let file = OpenOptions::new().append(true).open(&path)?;
+                if file.metadata()?.len() > len {
+                    warn!(len, "operation log ends with an incomplete record, drop it");
+                    file.set_len(len)?;
+                }
+                info!(records, elapsed = ?started.elapsed(), "replayed operation log");
+                (file, len)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let (tmp, file) = dump(&path, cache)?;
+                install(&tmp, &path, file, &[])?
+            }
+            Err(err) => return Err(err),
+        };
+        let inner = Arc::new(Mutex::new(Inner {
+            file: Arc::new(file),
+            len,
+            compacted_len: len,
+            dirty: false,
+            rewrite: None,
+        }));
+        let (writes, writes_rx) = mpsc::channel();
+        let writing = inner.clone();
+        thread::Builder::new()
+            .name("memcrab-oplog".to_owned())
+            .spawn(move || write(&writing, fsync, writes_rx))?;
+        Ok(Self {
+            path,
+            compaction_size,
+            inner,
+            writes,
+        })
+    }
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        lock(&self.inner)
+    }
+    /// Append records made by [`encode`] or [`clear`].
+    /// The caller keeps the records of a key in the order of its changes.
+    pub fn append(&self, records: Arc<[u8]>) -> Written {
+        let (written, written_rx) = oneshot::channel();
+        // Fails only if the writer has panicked, then the receiver reports it.
+        let _ = self.writes.send((records, written));
+        written_rx
+    }
+    /// Flush the records written since the last call, with [`Fsync::EverySecond`].
+    pub fn sync(&self) -> io::Result<()> {
+        let file = {
+            let mut inner = self.lock();
+            if !inner.dirty {
+                return Ok(());
+            }
+            inner.dirty = false;
+            inner.file.clone()
+        };
+        file.sync_data()
+    }
+    pub fn needs_compaction(&self) -> bool {
+        let inner = self.lock();
+        let threshold = self.compaction_size.max(2 * inner.compacted_len);
+        inner.rewrite.is_none() && inner.len >= threshold
+    }
+    /// Rewrite the log from the current state of `cache`.
+    /// Changes go on meanwhile, only the end of a compaction holds the log.
+    /// Returns `false` if another compaction is running already.
+    pub fn compact(&self, cache: &Cache) -> io::Result<bool> {
+        {
+            let mut inner = self.lock();
+            if inner.rewrite.is_some() {
+                return Ok(false);
+            }
+            inner.rewrite = Some(vec![]);
+        }
+        let started = Instant::now();
+        let mut tmp = None;
+        let mut compact = || {
+            let (path, file) = dump(&self.path, cache)?;
+            let path = tmp.insert(path);
+            file.sync_data()?;
+
+            let mut inner = self.lock();
+            let rewrite = inner.rewrite.take().unwrap_or_default();
+            let (file, len) = install(path, &self.path, file, &rewrite)?;
+            inner.file = Arc::new(file);
+            inner.len = len;
+            inner.compacted_len = len;
+            inner.dirty = false;
+            Ok(len)
+        };
+        match compact() {
+            Ok(len) => {
+                info!(len, elapsed = ?started.elapsed(), "compacted operation log");
+                Ok(true)
+            }
+            Err(err) => {
+                self.lock().rewrite = None;
+                if let Some(tmp) = tmp {
+                    let _ = fs::remove_file(tmp);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+fn lock(inner: &Mutex<Inner>) -> MutexGuard<'_, Inner> {
+    inner.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Everything that is appended while a batch is written goes into the next batch,
+// so that a busy log writes and flushes less often than there are changes.
+fn write(inner: &Mutex<Inner>, fsync: Fsync, writes: mpsc::Receiver<Append>) {
+    let mut batch = vec![];
+    while let Ok(first) = writes.recv() {
+        let mut waiting = vec![first];
+        waiting.extend(writes.try_iter());
+        batch.clear();
+        for (records, _) in &waiting {
+            batch.extend_from_slice(records);
+        }
+        let result = write_batch(inner, fsync, &batch);
+        for (_, written) in waiting {
+            let result = match &result {
+                Ok(()) => Ok(()),
+                Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            };
+            let _ = written.send(result);
+        }
+    }
+}
+
+fn write_batch(inner: &Mutex<Inner>, fsync: Fsync, batch: &[u8]) -> io::Result<()> {
+    let file = {
+        let mut inner = lock(inner);
+        if let Err(err) = (&*inner.file).write_all(batch) {
+            // Don't leave a partial record in front of the next ones.
+            let _ = inner.file.set_len(inner.len);
+            return Err(err);
+        }
+        inner.len += batch.len() as u64;
+        if let Some(rewrite) = &mut inner.rewrite {
+            rewrite.extend_from_slice(batch);
+        }
+        match fsync {
+            Fsync::Always => inner.file.clone(),
+            Fsync::EverySecond => {
+                inner.dirty = true;
+                return Ok(());
+            }
+            Fsync::Never => return Ok(()),
+        }
+    };
+    // Not under the lock, a compaction may install a new file meanwhile.
+    // Then the batch is in its tail, which is flushed when it's installed.
+    file.sync_data()
+}
+
+/// Records of the state of `keys` in `cache`, sent under the lock of their segment,
+/// so that records of a key are sent in the order of its changes.
+/// Keys of one segment are sent together.
+pub(crate) fn encode(cache: &Cache, keys: &[Vec<u8>], mut send: impl FnMut(Vec<u8>)) {
+    cache.inner.lock_groups_and_then(keys, |segment, keys| {
+        let mut buf = vec![];
+        for key in keys {
+            encode_state(&mut buf, segment, key);
+        }
+        send(buf);
+    });
+}
+
+/// Clear `cache` and send the record of it, under the locks of all segments,
+/// so that no change of a key gets between the two.
+pub(crate) fn clear(cache: &Cache, send: impl FnOnce(Vec<u8>)) {
+    cache.inner.clear_and_then(|| send(vec![CLEAR]));
+}
+
+/// Apply the complete records made by [`encode`] at the start of `records` to `cache`.
+/// Returns their length, the rest is the start of a record that is cut off.
+pub(crate) fn apply(records: &[u8], cache: &Cache) -> io::Result<usize> {
+    let mut reader = records;
+    loop {
+        let complete = records.len() - reader.len();
+        match apply_record(&mut reader, cache) {
+            Ok(true) => {}
+            Ok(false) => return Ok(complete),
+            // A record is read whole before it's applied.
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(complete),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn encode_state(buf: &mut Vec<u8>, segment: &MemLru<Vec<u8>, Value>, key: &[u8]) {
+    match segment.peek(key).filter(|val| !val.expired()) {
+        Some(val) => {
+            match val.ttl() {
+                Some(ttl) => {
+                    let deadline = SystemTime::now() + ttl;
+                    buf.push(PUT_EXPIRING);
+                    buf.extend(unix_millis(deadline).to_be_bytes());
+                }
+                None => buf.push(PUT),
+            }
+            put_bytes(buf, key);
+            put_bytes(buf, val.as_slice());
+        }
+        None => {
+            buf.push(REMOVE);
+            put_bytes(buf, key);
+        }
+    }
+}
+
+// The format of `write_bytes`, which never fails for a `Vec`.
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend((bytes.len() as u64).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+// Returns the length of the complete records and how many there are.
+fn replay(reader: impl Read, cache: &Cache) -> io::Result<(u64, usize)> {
+    let mut reader = Counting {
+        inner: reader,
+        read: 0,
+    };
+    cache.restore(&mut reader)?;
+    let mut records = 0;
+    loop {
+        let start = reader.read;
+        match apply_record(&mut reader, cache) {
+            Ok(true) => records += 1,
+            Ok(false) => return Ok((start, records)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok((start, records)),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// Returns `false` at the end of the log.
+fn apply_record(reader: &mut impl Read, cache: &Cache) -> io::Result<bool> {
+    let mut tag = [0];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(false);
+    }
+    match tag[0] {
+        PUT => {
+            let key = read_bytes(reader)?;
+            let value = read_bytes(reader)?;
+            let _ = cache.set(key, value);
+        }
+        PUT_EXPIRING => {
+            let deadline = read_u64(reader)?;
+            let key = read_bytes(reader)?;
+            let value = read_bytes(reader)?;
+            match deadline.checked_sub(unix_millis(SystemTime::now())) {
+                Some(ttl) if ttl > 0 => {
+                    let _ = cache.set_with_expiration(key, value, Duration::from_millis(ttl));
+                }
+                _ => {
+                    cache.remove(&key);
+                }
+            }
+        }
+        REMOVE => {
+            cache.remove(&read_bytes(reader)?);
+        }
+        CLEAR => cache.clear(),
+        tag => return Err(invalid_data(format!("unknown operation log record {tag}"))),
+    }
+    Ok(true)
+}
+
+// Write the state of `cache` to a new file next to `path`.
+fn dump(path: &Path, cache: &Cache) -> io::Result<(PathBuf, File)> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let _ = fs::remove_file(&tmp);
+    // Appending, so that a truncated write is followed by the next record, not by a gap.
+    let file = OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .open(&tmp)?;
+    let mut writer = BufWriter::new(file);
+    cache.dump(&mut writer)?;
+    let file = writer.into_inner()?;
+    Ok((tmp, file))
+}
+
+// Add `tail` to the dump and put it in place of the log.
+fn install(tmp: &Path, path: &Path, file: File, tail: &[u8]) -> io::Result<(File, u64)> {
+    (&file).write_all(tail)?;
+    file.sync_data()?;
+    fs::rename(tmp, path)?;
+    let len = file.metadata()?.len();
+    Ok((file, len))
+}
+
+struct Counting<R> {
+    inner: R,
+    read: u64,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,36 @@
+use core::hash::Hash;
+use lru::LruCache;
+
+use super::EvictionPolicy;
+
+/// Evicts the item that was inserted first.
+pub struct Fifo<K: Hash + Eq> {
+    // Only inserted to and removed from, so the least recent entry is the oldest.
+    order: LruCache<K, ()>,
+}
+
+impl<K: Hash + Eq> Default for Fifo<K> {
+    fn default() -> Self {
+        Self {
+            order: LruCache::unbounded(),
+        }
+    }
+}
+
+impl<K> EvictionPolicy<K> for Fifo<K>
+where
+    K: Hash + Eq + Clone + Send,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.order.push(key.clone(), ());
+    }
+    fn on_remove(&mut self, key: &K) {
+        self.order.pop(key);
+    }
+    fn victim(&mut self) -> Option<K> {
+        self.order.peek_lru().map(|(key, _)| key.clone())
+    }
+    fn clear(&mut self) {
+        self.order.clear();
+    }
+}
@@ -0,0 +1,72 @@
+use core::hash::Hash;
+use lru::LruCache;
+use std::collections::{BTreeMap, HashMap};
+
+use super::EvictionPolicy;
+
+/// Evicts the item with the fewest uses, the least recently used one among equals.
+pub struct Lfu<K: Hash + Eq> {
+    uses: HashMap<K, u64>,
+    // Keys by their number of uses, each bucket in the order of use.
+    buckets: BTreeMap<u64, LruCache<K, ()>>,
+}
+
+impl<K: Hash + Eq> Default for Lfu<K> {
+    fn default() -> Self {
+        Self {
+            uses: HashMap::new(),
+            buckets: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Lfu<K> {
+    fn take(&mut self, uses: u64, key: &K) -> Option<K> {
+        let bucket = self.buckets.get_mut(&uses)?;
+        let (key, ()) = bucket.pop_entry(key)?;
+        if bucket.is_empty() {
+            self.buckets.remove(&uses);
+        }
+        Some(key)
+    }
+    fn put(&mut self, uses: u64, key: K) {
+        self.buckets
+            .entry(uses)
+            .or_insert_with(LruCache::unbounded)
+            .push(key, ());
+    }
+}
+
+impl<K> EvictionPolicy<K> for Lfu<K>
+where
+    K: Hash + Eq + Clone + Send,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.uses.insert(key.clone(), 1);
+        self.put(1, key.clone());
+    }
+    fn on_access(&mut self, key: &K) {
+        let Some(uses) = self.uses.get_mut(key) else {
+            return;
+        };
+        let old = *uses;
+        *uses = old.saturating_add(1);
+        let new = *uses;
+        if let Some(key) = self.take(old, key) {
+            self.put(new, key);
+        }
+    }
+    fn on_remove(&mut self, key: &K) {
+        if let Some(uses) = self.uses.remove(key) {
+            self.take(uses, key);
+        }
+    }
+    fn victim(&mut self) -> Option<K> {
+        let (_, bucket) = self.buckets.first_key_value()?;
+        bucket.peek_lru().map(|(key, _)| key.clone())
+    }
+    fn clear(&mut self) {
+        self.uses.clear();
+        self.buckets.clear();
+    }
+}
@@ -0,0 +1,65 @@
+mod fifo;
+mod lfu;
+mod sketch;
+mod tinylfu;
+
+use core::hash::Hash;
+
+pub use fifo::Fifo;
+pub use lfu::Lfu;
+pub use tinylfu::TinyLfu;
+
+/// Decides which item of a full segment is evicted next.
+///
+/// A segment keeps its items in the order they were used, which is all that LRU needs.
+/// Other policies track the keys they need themselves, through these notifications.
+pub trait EvictionPolicy<K>: Send {
+    /// A new item was stored.
+    fn on_insert(&mut self, _key: &K) {}
+    /// An item was read or modified.
+    fn on_access(&mut self, _key: &K) {}
+    /// An item left the segment, for any reason.
+    fn on_remove(&mut self, _key: &K) {}
+    /// The key to evict next, `None` for the least recently used one.
+    fn victim(&mut self) -> Option<K> {
+        None
+    }
+    fn clear(&mut self) {}
+}
+
+/// Evicts the least recently used item.
+#[derive(Debug, Default)]
+pub struct Lru;
+
+impl<K> EvictionPolicy<K> for Lru {}
+
+/// Which items a full cache evicts to make room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eviction {
+    /// The least recently used item.
+    #[default]
+    Lru,
+    /// The least frequently used item, the least recently used one among equals.
+    Lfu,
+    /// The oldest item, reads don't matter.
+    Fifo,
+    /// W-TinyLFU: new items get into a small LRU window, and leave it for the main part
+    /// only if they are used more often than the item they would push out.
+    /// One-off reads, e.g. of a full scan, don't flush the items that are used all the time.
+    TinyLfu,
+}
+
+impl Eviction {
+    /// `capacity` is the expected number of items in a segment.
+    pub(super) fn policy<K>(self, capacity: usize) -> Box<dyn EvictionPolicy<K>>
+    where
+        K: Hash + Eq + Clone + Send + 'static,
+    {
+        match self {
+            Eviction::Lru => Box::new(Lru),
+            Eviction::Lfu => Box::<Lfu<K>>::default(),
+            Eviction::Fifo => Box::<Fifo<K>>::default(),
+            Eviction::TinyLfu => Box::new(TinyLfu::with_capacity(capacity)),
+        }
+    }
+}
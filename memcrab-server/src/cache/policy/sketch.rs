@@ -0,0 +1,60 @@
+/// Count-min sketch of how often keys were used recently, with counters that stop at 15.
+///
+/// All counters are halved once the number of recorded uses reaches ten times the capacity,
+/// so that keys that were popular long ago fade out.
+pub struct Sketch {
+    table: Vec<u8>,
+    width: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+const DEPTH: usize = 4;
+const MAX_COUNT: u8 = 15;
+const SEEDS: [u64; DEPTH] = [
+    0x9E37_79B9_7F4A_7C15,
+    0xC2B2_AE3D_27D4_EB4F,
+    0x1656_67B1_9E37_79F9,
+    0x85EB_CA77_C2B2_AE63,
+];
+
+impl Sketch {
+    pub fn with_capacity(capacity: usize) -> Self {
+        // Wide enough for the keys of a scan that passes by not to add up.
+        let width = capacity
+            .saturating_mul(8)
+            .clamp(64, 1 << 20)
+            .next_power_of_two();
+        Self {
+            table: vec![0; width * DEPTH],
+            width,
+            additions: 0,
+            sample_size: capacity.saturating_mul(10).max(width),
+        }
+    }
+    pub fn increment(&mut self, hash: u64) {
+        for at in self.indexes(hash) {
+            if self.table[at] < MAX_COUNT {
+                self.table[at] += 1;
+            }
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.table.iter_mut().for_each(|count| *count /= 2);
+            self.additions /= 2;
+        }
+    }
+    pub fn estimate(&self, hash: u64) -> u8 {
+        self.indexes(hash)
+            .map(|at| self.table[at])
+            .min()
+            .unwrap_or_default()
+    }
+    fn indexes(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let width = self.width;
+        SEEDS.into_iter().enumerate().map(move |(row, seed)| {
+            let mixed = hash.wrapping_mul(seed);
+            row * width + (mixed >> 32) as usize % width
+        })
+    }
+}
@@ -0,0 +1,107 @@
+use core::hash::{BuildHasher, Hash};
+use lru::LruCache;
+use std::collections::hash_map::RandomState;
+
+use super::{sketch::Sketch, EvictionPolicy};
+
+/// W-TinyLFU.
+///
+/// New items enter a window that holds 1% of the items and is evicted in LRU order.
+/// While the segment has room, items leave the window for the main part freely.
+/// Once it's full, an item that leaves the window competes with the next victim
+/// of the main part, and the one that was used less often recently is evicted. The main part is a segmented LRU:
+/// items that are used again on probation become protected, which is 80% of the main part.
+pub struct TinyLfu<K: Hash + Eq> {
+    sketch: Sketch,
+    hasher: RandomState,
+    window: LruCache<K, ()>,
+    probation: LruCache<K, ()>,
+    protected: LruCache<K, ()>,
+}
+
+impl<K: Hash + Eq> TinyLfu<K> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            sketch: Sketch::with_capacity(capacity),
+            hasher: RandomState::new(),
+            window: LruCache::unbounded(),
+            probation: LruCache::unbounded(),
+            protected: LruCache::unbounded(),
+        }
+    }
+    fn frequency(&self, key: &K) -> u8 {
+        self.sketch.estimate(self.hasher.hash_one(key))
+    }
+    fn window_len(&self) -> usize {
+        let len = self.window.len() + self.probation.len() + self.protected.len();
+        (len / 100).max(1)
+    }
+    fn protected_is_full(&self) -> bool {
+        let main = self.probation.len() + self.protected.len();
+        self.protected.len() > main * 4 / 5
+    }
+}
+
+impl<K> EvictionPolicy<K> for TinyLfu<K>
+where
+    K: Hash + Eq + Clone + Send,
+{
+    fn on_insert(&mut self, key: &K) {
+        self.sketch.increment(self.hasher.hash_one(key));
+        self.window.push(key.clone(), ());
+        while self.window.len() > self.window_len() {
+            let Some((key, ())) = self.window.pop_lru() else {
+                break;
+            };
+            self.probation.push(key, ());
+        }
+    }
+    fn on_access(&mut self, key: &K) {
+        self.sketch.increment(self.hasher.hash_one(key));
+        // `get` moves the key to the front.
+        if self.window.get(key).is_some() || self.protected.get(key).is_some() {
+            return;
+        }
+        if let Some((key, ())) = self.probation.pop_entry(key) {
+            self.protected.push(key, ());
+            while self.protected_is_full() {
+                let Some((demoted, ())) = self.protected.pop_lru() else {
+                    break;
+                };
+                self.probation.push(demoted, ());
+            }
+        }
+    }
+    fn on_remove(&mut self, key: &K) {
+        let _ = self.window.pop(key).is_some()
+            || self.probation.pop(key).is_some()
+            || self.protected.pop(key).is_some();
+    }
+    fn victim(&mut self) -> Option<K> {
+        let main = self
+            .probation
+            .peek_lru()
+            .or_else(|| self.protected.peek_lru());
+        let main = main.map(|(key, _)| key.clone());
+        let candidate = self.window.peek_lru().map(|(key, _)| key.clone());
+        match (candidate, main) {
+            (Some(candidate), Some(main)) if self.window.len() >= self.window_len() => {
+                if self.frequency(&candidate) > self.frequency(&main) {
+                    // Admitted, the victim is on probation now.
+                    self.window.pop(&candidate);
+                    self.probation.push(candidate, ());
+                    Some(main)
+                } else {
+                    Some(candidate)
+                }
+            }
+            (_, Some(main)) => Some(main),
+            (candidate, None) => candidate,
+        }
+    }
+    fn clear(&mut self) {
+        self.window.clear();
+        self.probation.clear();
+        self.protected.clear();
+    }
+}
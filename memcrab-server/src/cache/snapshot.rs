@@ -0,0 +1,171 @@
+//! Snapshot format, all numbers are big-endian:
+//!
+//! ```text
+//! b"memcrab", u8 (format version), u64 (Unix time of the snapshot in milliseconds),
+//! (1, KeyLen, Key, ValueLen, Value | 2, u64 (milliseconds to live), KeyLen, Key, ValueLen, Value)*,
+//! 0
+//! ```
+//!
+//! Items are written from the least to the most recently used, one segment after another.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{Cache, Value};
+
+const MAGIC: &[u8; 7] = b"memcrab";
+const FORMAT_VERSION: u8 = 1;
+
+const END: u8 = 0;
+const ITEM: u8 = 1;
+const EXPIRING_ITEM: u8 = 2;
+
+impl Cache {
+    /// Write every live item with its remaining time to live to `path`.
+    /// The file is replaced only once the snapshot is complete. Returns the number of items.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let mut tmp = OsString::from(path.as_os_str());
+        tmp.push(".tmp");
+
+        let write = || {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            let written = self.dump(&mut file)?;
+            file.into_inner()?.sync_all()?;
+            fs::rename(&tmp, path)?;
+            Ok(written)
+        };
+        write().inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+    /// Store the items of a snapshot written by [`Cache::save`].
+    /// The time since the snapshot was written is subtracted from the time to live of every item.
+    /// Returns the number of items stored, expired and too big items are skipped.
+    pub fn load(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        self.restore(BufReader::new(File::open(path)?))
+    }
+    /// Write a snapshot of every live item. Segments are locked one at a time,
+    /// so the snapshot is consistent per segment, not across segments.
+    pub fn dump(&self, mut writer: impl Write) -> io::Result<usize> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&unix_millis(SystemTime::now()).to_be_bytes())?;
+
+        let mut written = 0;
+        // Copy a segment out, so that requests don't wait for the writer.
+        let segments = self.inner.segments_and_then(|segment| {
+            segment
+                .iter()
+                .rev()
+                .filter(|(_, val)| !val.expired())
+                .map(|(key, val)| (key.clone(), val.clone()))
+                .collect::<Vec<_>>()
+        });
+        for items in segments {
+            for (key, val) in items {
+                match val.ttl().map(|ttl| ttl.as_millis()) {
+                    // Expires within a millisecond.
+                    Some(0) => continue,
+                    Some(millis) => {
+                        let millis = u64::try_from(millis).unwrap_or(u64::MAX);
+                        writer.write_all(&[EXPIRING_ITEM])?;
+                        writer.write_all(&millis.to_be_bytes())?;
+                    }
+                    None => writer.write_all(&[ITEM])?,
+                }
+                write_bytes(&mut writer, &key)?;
+                write_bytes(&mut writer, val.as_slice())?;
+                written += 1;
+            }
+        }
+        writer.write_all(&[END])?;
+        writer.flush()?;
+        Ok(written)
+    }
+    /// Store the items of a snapshot written by [`Cache::dump`].
+    /// An invalid or truncated snapshot is an error, the items read before it are kept.
+    pub fn restore(&self, mut reader: impl Read) -> io::Result<usize> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a memcrab snapshot"));
+        }
+        let version = read_u8(&mut reader)?;
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {version}"
+            )));
+        }
+        let saved_at = read_u64(&mut reader)?;
+        let elapsed =
+            Duration::from_millis(unix_millis(SystemTime::now()).saturating_sub(saved_at));
+
+        let mut restored = 0;
+        loop {
+            let ttl = match read_u8(&mut reader)? {
+                END => return Ok(restored),
+                ITEM => None,
+                EXPIRING_ITEM => Some(Duration::from_millis(read_u64(&mut reader)?)),
+                tag => return Err(invalid_data(format!("unknown snapshot entry {tag}"))),
+            };
+            let key = read_bytes(&mut reader)?;
+            let value = read_bytes(&mut reader)?;
+            let value = match ttl {
+                Some(ttl) if ttl <= elapsed => continue,
+                Some(ttl) => Value::with_expiration(value, ttl - elapsed),
+                None => Value::new(value),
+            };
+            if self._set(key, value).is_ok() {
+                restored += 1;
+            }
+        }
+    }
+}
+
+pub(super) fn unix_millis(time: SystemTime) -> u64 {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
+pub(super) fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+pub(super) fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(super) fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+// The buffer grows while reading, so a corrupt length can't allocate more than the file holds.
+pub(super) fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    // The byte size of an item is its capacity, which has grown while reading.
+    bytes.shrink_to_fit();
+    Ok(bytes)
+}
+
+pub(super) fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
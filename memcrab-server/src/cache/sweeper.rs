@@ -0,0 +1,23 @@
+use std::{sync::Weak, time::Duration};
+use tracing::{debug, warn};
+
+use super::{map::Map, Value};
+
+// Removals from one segment under one lock.
+const BATCH: usize = 256;
+
+/// Remove expired items every `interval`, until the map is dropped.
+/// A sweep locks the segments many times, so it runs on a blocking thread.
+pub(super) async fn sweep(map: Weak<Map<Vec<u8>, Value>>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(map) = map.upgrade() else {
+            return;
+        };
+        match tokio::task::spawn_blocking(move || map.remove_expired(BATCH)).await {
+            Ok(0) => {}
+            Ok(removed) => debug!(removed, "removed expired items"),
+            Err(err) => warn!(error = %err, "cannot remove expired items"),
+        }
+    }
+}
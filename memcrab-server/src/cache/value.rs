@@ -1,44 +1,95 @@
-use std::{num::NonZeroU32, time::Instant};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
-use super::ByteSized;
+use super::{
+    memlru::{Expiring, Versioned},
+    ByteSized,
+};
 
 #[derive(Clone, Debug)]
 struct Clock {
-    start: Instant,
-    expiration: u32,
+    deadline: Instant,
 }
 
 impl Clock {
-    fn start_timing(expiration: NonZeroU32) -> Self {
-        let start = Instant::now();
-        Self {
-            start,
-            expiration: expiration.into(),
-        }
+    /// `None` if the deadline is too far away to be represented, which is as good as never.
+    fn start_timing(ttl: Duration) -> Option<Self> {
+        let deadline = Instant::now().checked_add(ttl)?;
+        Some(Self { deadline })
     }
+    // A value is gone from the very moment its time to live runs out.
     fn expired(&self) -> bool {
-        let passed = self.start.elapsed().as_secs() as u32;
-        passed > self.expiration
+        Instant::now() >= self.deadline
+    }
+    fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
     }
 }
 
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+// Every new value gets a version that is greater than all versions before it.
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
 #[derive(Clone, Debug)]
 pub struct Value {
     inner: Vec<u8>,
     clock: Option<Clock>,
+    version: u64,
 }
 
 impl Value {
     pub fn new(inner: Vec<u8>) -> Self {
-        Self { inner, clock: None }
+        Self {
+            inner,
+            clock: None,
+            version: next_version(),
+        }
+    }
+    /// The value expires `ttl` from now, a zero `ttl` expires it at once.
+    pub fn with_expiration(inner: Vec<u8>, ttl: Duration) -> Self {
+        let clock = Clock::start_timing(ttl);
+        Self {
+            inner,
+            clock,
+            version: next_version(),
+        }
     }
-    pub fn with_expiration(inner: Vec<u8>, expiration: NonZeroU32) -> Self {
-        let clock = Some(Clock::start_timing(expiration));
-        Self { inner, clock }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.inner
+    }
+    /// Replace the bytes, keeping the expiration. This is a new version of the value.
+    pub fn replace(&mut self, inner: Vec<u8>) {
+        self.inner = inner;
+        self.version = next_version();
+    }
+    pub fn append(&mut self, bytes: &[u8]) {
+        // Reserve exactly, the byte size of a value is its capacity.
+        self.inner.reserve_exact(bytes.len());
+        self.inner.extend_from_slice(bytes);
+        self.version = next_version();
+    }
+    pub fn prepend(&mut self, bytes: &[u8]) {
+        let mut inner = Vec::with_capacity(bytes.len() + self.inner.len());
+        inner.extend_from_slice(bytes);
+        inner.extend_from_slice(&self.inner);
+        self.replace(inner);
     }
     pub fn expired(&self) -> bool {
         self.clock.as_ref().map(|c| c.expired()).unwrap_or(false)
     }
+    /// Remaining time to live, `None` if the value never expires.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.clock.as_ref().map(|c| c.remaining())
+    }
+    /// Restart the expiration from now, or remove it.
+    pub fn set_expiration(&mut self, ttl: Option<Duration>) {
+        self.clock = ttl.and_then(Clock::start_timing);
+    }
     pub fn into_vec(self) -> Vec<u8> {
         self.inner
     }
@@ -49,3 +100,15 @@ impl ByteSized for Value {
         self.inner.bytesize()
     }
 }
+
+impl Expiring for Value {
+    fn deadline(&self) -> Option<Instant> {
+        self.clock.as_ref().map(|c| c.deadline)
+    }
+}
+
+impl Versioned for Value {
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
@@ -25,15 +25,23 @@ async fn main() {
 mod cache;
 mod serve;
 
-pub use cache::Cache;
-pub use serve::{serve, AcceptConnection};
+pub use cache::{
+    AppendError, Cache, CasOutcome, CounterError, Eviction, Fsync, SegmentStats, TooBig,
+};
+pub use serve::{
+    serve, serve_with_cfg, serve_with_shutdown, AcceptConnection, ConnectionOverflow, ServerCfg,
+    ServerHandle,
+};
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use memcrab_protocol::{Msg, Request, Response, Socket};
-    use tokio::net::{TcpListener, TcpStream};
+    use memcrab_protocol::{Expiration, Item, Msg, Request, Response, Socket};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
 
     use super::*;
 
@@ -64,7 +72,7 @@ mod tests {
         let msg = socket.recv().await.unwrap();
         assert_eq!(msg, Msg::Response(Response::Pong));
 
-        let key = "some-key".to_string();
+        let key = b"some-key".to_vec();
         socket
             .send(Msg::Request(Request::Get(key.clone())))
             .await
@@ -77,7 +85,7 @@ mod tests {
             .send(Msg::Request(Request::Set {
                 key: key.clone(),
                 value: value.clone(),
-                expiration: 0,
+                expiration: Expiration::Never,
             }))
             .await
             .unwrap();
@@ -91,4 +99,717 @@ mod tests {
         let msg = socket.recv().await.unwrap();
         assert_eq!(msg, Msg::Response(Response::Value(value.clone())));
     }
+
+    #[test]
+    fn test_binary_keys() {
+        let cache: Cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let key = vec![0, 159, 146, 150, 255];
+        cache.set(key.clone(), vec![1]).unwrap();
+        assert_eq!(cache.get(&key), Some(vec![1]));
+        assert_eq!(cache.get(b"other"), None);
+        assert_eq!(cache.remove(&key), Some(vec![1]));
+        assert_eq!(cache.get(&key), None);
+    }
+
+    #[test]
+    fn test_batch() {
+        let cache: Cache = Cache::builder()
+            .segments(4)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let keys: Vec<Vec<u8>> = (0u8..10).map(|i| vec![i]).collect();
+        let items = keys
+            .iter()
+            .map(|key| (key.clone(), key.repeat(2), None))
+            .collect();
+        cache.set_many(items).unwrap();
+
+        let mut requested = keys.clone();
+        requested.push(b"missing".to_vec());
+        let values = cache.get_many(&requested);
+        assert_eq!(values.len(), requested.len());
+        for (key, value) in keys.iter().zip(&values) {
+            assert_eq!(value.as_deref(), Some(key.repeat(2).as_slice()));
+        }
+        assert_eq!(values.last(), Some(&None));
+
+        let removed = cache.remove_many(&[&keys[3][..], b"missing", &keys[3][..]]);
+        assert_eq!(removed, vec![Some(vec![3, 3]), None, None]);
+        assert_eq!(cache.get(&keys[3]), None);
+        assert_eq!(cache.get(&keys[4]), Some(vec![4, 4]));
+    }
+
+    #[test]
+    fn test_cas() {
+        let cache: Cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let key = b"key".to_vec();
+        let outcome = cache.compare_and_swap(key.clone(), vec![0], None, 1);
+        assert_eq!(outcome, Ok(CasOutcome::NotFound));
+
+        cache.set(key.clone(), vec![1]).unwrap();
+        let (value, version) = cache.get_with_version(&key).unwrap();
+        assert_eq!(value, vec![1]);
+
+        let outcome = cache.compare_and_swap(key.clone(), vec![2], None, version);
+        assert_eq!(outcome, Ok(CasOutcome::Stored));
+        let (value, new_version) = cache.get_with_version(&key).unwrap();
+        assert_eq!(value, vec![2]);
+        assert!(new_version > version);
+
+        let outcome = cache.compare_and_swap(key.clone(), vec![3], None, version);
+        assert_eq!(outcome, Ok(CasOutcome::VersionMismatch));
+        assert_eq!(cache.get(&key), Some(vec![2]));
+    }
+
+    #[test]
+    fn test_counter() {
+        let cache: Cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let key = b"hits".to_vec();
+        let err = cache.increment(key.clone(), 1, None).unwrap_err();
+        assert_eq!(err, CounterError::NotFound);
+
+        assert_eq!(cache.increment(key.clone(), 1, Some((10, None))), Ok(10));
+        assert_eq!(cache.increment(key.clone(), 5, Some((10, None))), Ok(15));
+        assert_eq!(cache.get(&key), Some(b"15".to_vec()));
+        assert_eq!(cache.decrement(key.clone(), 20, None), Ok(0));
+
+        cache
+            .set(key.clone(), u64::MAX.to_string().into_bytes())
+            .unwrap();
+        assert_eq!(cache.increment(key.clone(), 2, None), Ok(1));
+
+        for invalid in [&b"abc"[..], b"-1", b"", b"+1", b"18446744073709551616"] {
+            cache.set(key.clone(), invalid.to_vec()).unwrap();
+            let err = cache.increment(key.clone(), 1, None).unwrap_err();
+            assert_eq!(err, CounterError::NotCounter);
+            assert_eq!(cache.get(&key), Some(invalid.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_conditional_set() {
+        let cache: Cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let key = b"lock".to_vec();
+        assert_eq!(cache.replace(key.clone(), vec![0], None), Ok(false));
+        assert_eq!(cache.get(&key), None);
+
+        assert_eq!(cache.add(key.clone(), vec![1], None), Ok(true));
+        assert_eq!(cache.add(key.clone(), vec![2], None), Ok(false));
+        assert_eq!(cache.get(&key), Some(vec![1]));
+
+        assert_eq!(cache.replace(key.clone(), vec![3], None), Ok(true));
+        assert_eq!(cache.get(&key), Some(vec![3]));
+    }
+
+    #[test]
+    fn test_append() {
+        let cache: Cache = Cache::builder().segments(1).max_bytesize(64).build().into();
+
+        let key = b"events".to_vec();
+        assert_eq!(cache.append(&key, b"x"), Err(AppendError::NotFound));
+
+        cache.set(key.clone(), b"b".to_vec()).unwrap();
+        cache.append(&key, b"cd").unwrap();
+        cache.prepend(&key, b"a").unwrap();
+        assert_eq!(cache.get(&key), Some(b"abcd".to_vec()));
+
+        // growing the value evicts less recently used items
+        cache.set(b"old".to_vec(), vec![0; 30]).unwrap();
+        cache.append(&key, &[0; 30]).unwrap();
+        assert_eq!(cache.get(b"old"), None);
+        assert_eq!(cache.get(&key).map(|v| v.len()), Some(34));
+
+        assert_eq!(cache.append(&key, &[0; 30]), Err(AppendError::TooBig));
+        assert_eq!(cache.get(&key).map(|v| v.len()), Some(34));
+
+        // the oldest item grows, the newer one makes room for it
+        let cache: Cache = Cache::builder()
+            .segments(1)
+            .max_bytesize(64)
+            .eviction(Eviction::Fifo)
+            .build()
+            .into();
+        cache.set(key.clone(), vec![0; 4]).unwrap();
+        cache.set(b"new".to_vec(), vec![0; 30]).unwrap();
+        cache.append(&key, &[0; 30]).unwrap();
+        assert_eq!(cache.get(b"new"), None);
+        assert_eq!(cache.get(&key).map(|v| v.len()), Some(34));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache: Cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let key = b"session".to_vec();
+        let minute = Duration::from_secs(60);
+        assert_eq!(cache.ttl(&key), None);
+        assert!(!cache.touch(&key, Some(minute)));
+
+        cache.set(key.clone(), vec![1]).unwrap();
+        assert_eq!(cache.ttl(&key), Some(None));
+
+        assert!(cache.touch(&key, Some(minute)));
+        let ttl = cache.ttl(&key).flatten().unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(59));
+
+        assert!(cache.persist(&key));
+        assert_eq!(cache.ttl(&key), Some(None));
+
+        assert_eq!(cache.get_and_touch(&key, Some(minute)), Some(vec![1]));
+        assert!(cache.ttl(&key).flatten().is_some());
+    }
+
+    #[test]
+    fn test_stats() {
+        let cache: Cache = Cache::builder().segments(1).max_bytesize(4).build().into();
+        cache.set(b"a".to_vec(), vec![1]).unwrap();
+        cache.set(b"b".to_vec(), vec![2]).unwrap();
+        cache.set(b"c".to_vec(), vec![3]).unwrap();
+        assert_eq!(cache.get(b"a"), None);
+        assert_eq!(cache.get(b"c"), Some(vec![3]));
+
+        let [stats] = cache.stats()[..] else {
+            panic!("one segment");
+        };
+        assert_eq!((stats.hits(), stats.misses()), (1, 1));
+        assert_eq!((stats.sets(), stats.evictions()), (3, 1));
+        assert_eq!(
+            (stats.items(), stats.bytesize(), stats.max_bytesize()),
+            (2, 4, 4)
+        );
+
+        // the length limit evicts as well
+        let cache: Cache = Cache::builder()
+            .segments(1)
+            .max_len(1)
+            .max_bytesize(1024)
+            .build()
+            .into();
+        cache.set(b"a".to_vec(), vec![1]).unwrap();
+        cache.set(b"b".to_vec(), vec![2]).unwrap();
+        let stats = cache.stats()[0];
+        assert_eq!(
+            (stats.evictions(), stats.items(), stats.bytesize()),
+            (1, 1, 2)
+        );
+
+        // writes in place are sets, a new expiration is not
+        let cache: Cache = Cache::builder()
+            .segments(1)
+            .max_bytesize(1024)
+            .build()
+            .into();
+        cache.increment(b"n".to_vec(), 1, Some((0, None))).unwrap();
+        cache.increment(b"n".to_vec(), 1, None).unwrap();
+        cache.append(b"n", b"0").unwrap();
+        assert!(cache.touch(b"n", None));
+        assert_eq!(cache.stats()[0].sets(), 3);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let new_cache = |eviction: Eviction, max_len: usize| -> Cache {
+            Cache::builder()
+                .segments(1)
+                .max_len(max_len)
+                .max_bytesize(1 << 20)
+                .eviction(eviction)
+                .build()
+                .into()
+        };
+        let key = |i: u32| i.to_be_bytes().to_vec();
+        let present = |cache: &Cache, keys: &[u32]| -> Vec<bool> {
+            keys.iter().map(|&i| cache.get(&key(i)).is_some()).collect()
+        };
+
+        // 0 is read, 1 is read more often, then 2 makes room for itself
+        let evicted = |eviction| {
+            let cache = new_cache(eviction, 2);
+            cache.set(key(0), vec![0]).unwrap();
+            cache.set(key(1), vec![1]).unwrap();
+            cache.get(&key(1));
+            cache.get(&key(1));
+            cache.get(&key(0));
+            cache.set(key(2), vec![2]).unwrap();
+            present(&cache, &[0, 1])
+        };
+        assert_eq!(evicted(Eviction::Lru), [true, false]);
+        assert_eq!(evicted(Eviction::Lfu), [false, true]);
+        assert_eq!(evicted(Eviction::Fifo), [false, true]);
+
+        // a scan of cold keys doesn't flush a hot set
+        let hot_after_scan = |eviction| {
+            let cache = new_cache(eviction, 100);
+            for _ in 0..5 {
+                for i in 0..50 {
+                    cache.set(key(i), vec![1]).unwrap();
+                    cache.get(&key(i));
+                }
+            }
+            for i in 1000..2000 {
+                cache.set(key(i), vec![1]).unwrap();
+            }
+            present(&cache, &(0..50).collect::<Vec<_>>())
+                .into_iter()
+                .filter(|&hit| hit)
+                .count()
+        };
+        assert_eq!(hot_after_scan(Eviction::Lru), 0);
+        // Frequencies are estimated, a rare collision may cost a key.
+        assert!(hot_after_scan(Eviction::TinyLfu) >= 45);
+    }
+
+    #[tokio::test]
+    async fn test_sweeper() {
+        let cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1 << 20)
+            .sweep_interval(Some(Duration::from_millis(10)))
+            .build()
+            .into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, cache));
+        let mut socket = Socket::new(TcpStream::connect(addr).await.unwrap());
+        async fn call(socket: &mut Socket<TcpStream>, request: Request) -> Msg {
+            socket.send(Msg::Request(request)).await.unwrap();
+            socket.recv().await.unwrap()
+        }
+
+        let ttl = Expiration::After(Duration::from_millis(20));
+        let item = |key: &[u8], expiration| Item {
+            key: key.to_vec(),
+            value: vec![1],
+            expiration,
+        };
+        let mut items: Vec<_> = (0..1000u32).map(|i| item(&i.to_be_bytes(), ttl)).collect();
+        items.push(item(b"live", Expiration::Never));
+        items.push(item(b"later", Expiration::After(Duration::from_secs(60))));
+        items.push(item(b"kept", ttl));
+        let ok = Msg::Response(Response::Ok);
+        assert_eq!(call(&mut socket, Request::SetMany(items)).await, ok);
+        assert_eq!(
+            call(&mut socket, Request::Persist(b"kept".to_vec())).await,
+            ok
+        );
+
+        // removed without ever being read
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let Msg::Response(Response::Stats(stats)) = call(&mut socket, Request::Stats).await else {
+            panic!("stats");
+        };
+        let total = stats.total();
+        assert_eq!((total.items, total.expirations), (3, 1000));
+        assert_eq!(total.misses, 0);
+        let later = call(&mut socket, Request::Get(b"later".to_vec())).await;
+        assert_eq!(later, Msg::Response(Response::Value(vec![1])));
+    }
+
+    #[test]
+    fn test_no_sweeper_without_server() {
+        let cache: Cache = Cache::builder()
+            .segments(1)
+            .max_bytesize(1024)
+            .sweep_interval(Some(Duration::from_millis(10)))
+            .build()
+            .into();
+        let ttl = Duration::from_millis(10);
+        cache
+            .set_with_expiration(b"a".to_vec(), vec![1], ttl)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.stats()[0].items(), 1);
+        assert_eq!(cache.get(b"a"), None);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let new_cache = || -> Cache {
+            Cache::builder()
+                .segments(2)
+                .max_bytesize(1024)
+                .build()
+                .into()
+        };
+        let cache = new_cache();
+        cache.set(b"a".to_vec(), vec![1, 2]).unwrap();
+        cache
+            .set_with_expiration(b"b".to_vec(), vec![3], Duration::from_secs(60))
+            .unwrap();
+        cache
+            .set_with_expiration(b"c".to_vec(), vec![4], Duration::ZERO)
+            .unwrap();
+
+        let mut snapshot = vec![];
+        assert_eq!(cache.dump(&mut snapshot).unwrap(), 2);
+
+        let restored = new_cache();
+        assert_eq!(restored.restore(snapshot.as_slice()).unwrap(), 2);
+        assert_eq!(restored.get(b"a"), Some(vec![1, 2]));
+        assert_eq!(restored.get(b"b"), Some(vec![3]));
+        assert_eq!(restored.get(b"c"), None);
+        assert_eq!(restored.ttl(b"a"), Some(None));
+        let ttl = restored.ttl(b"b").unwrap().unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(50));
+
+        let err = new_cache()
+            .restore(&snapshot[..snapshot.len() - 2])
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        let err = new_cache().restore(&b"memcache"[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_oplog() {
+        use crate::cache::oplog::{self, OpLog};
+
+        let path = std::env::temp_dir().join(format!("memcrab-{}.oplog", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let new_cache = || -> Cache {
+            Cache::builder()
+                .segments(2)
+                .max_bytesize(1024)
+                .build()
+                .into()
+        };
+        let append = |log: &OpLog, records: Vec<u8>| {
+            log.append(records.into()).blocking_recv().unwrap().unwrap()
+        };
+        let record = |log: &OpLog, cache: &Cache, key: &[u8]| {
+            oplog::encode(cache, &[key.to_vec()], |records| append(log, records))
+        };
+
+        let cache = new_cache();
+        cache.set(b"old".to_vec(), vec![0]).unwrap();
+        let log = OpLog::open(&path, Fsync::Never, 0, &cache).unwrap();
+        cache.set(b"a".to_vec(), vec![1]).unwrap();
+        record(&log, &cache, b"a");
+        cache
+            .set_with_expiration(b"b".to_vec(), vec![2], Duration::from_secs(60))
+            .unwrap();
+        record(&log, &cache, b"b");
+        cache
+            .set_with_expiration(b"c".to_vec(), vec![3], Duration::from_millis(1))
+            .unwrap();
+        record(&log, &cache, b"c");
+        cache.remove(b"old");
+        record(&log, &cache, b"old");
+        drop(log);
+
+        // records of a replica
+        let mut records = vec![];
+        let keys = [b"a".to_vec(), b"b".to_vec()];
+        oplog::encode(&cache, &keys, |batch| records.extend(batch));
+        let replica = new_cache();
+        // the last record is cut off, it's applied with the rest
+        let applied = oplog::apply(&records[..records.len() - 1], &replica).unwrap();
+        assert!(replica.get(b"a").is_some() != replica.get(b"b").is_some());
+        let rest = &records[applied..];
+        assert_eq!(oplog::apply(rest, &replica).unwrap(), rest.len());
+        assert_eq!(replica.get(b"a"), Some(vec![1]));
+        assert_eq!(replica.get(b"b"), Some(vec![2]));
+
+        // a crash in the middle of a record
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, &[1, 0, 0]).unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+        let replayed = new_cache();
+        let log = OpLog::open(&path, Fsync::Always, 0, &replayed).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(replayed.get(b"a"), Some(vec![1]));
+        assert_eq!(replayed.get(b"b"), Some(vec![2]));
+        assert!(replayed.ttl(b"b").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(replayed.get(b"c"), None);
+        assert_eq!(replayed.get(b"old"), None);
+
+        for _ in 0..10 {
+            replayed.set(b"a".to_vec(), vec![4]).unwrap();
+            record(&log, &replayed, b"a");
+        }
+        assert!(log.needs_compaction());
+        assert!(log.compact(&replayed).unwrap());
+        assert!(std::fs::metadata(&path).unwrap().len() < len);
+        oplog::clear(&replayed, |records| append(&log, records));
+        drop(log);
+
+        let cleared = new_cache();
+        drop(OpLog::open(&path, Fsync::Never, 0, &cleared).unwrap());
+        assert_eq!(cleared.stats().iter().map(|s| s.items()).sum::<usize>(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_precise_expiration() {
+        let cache: Cache = Cache::builder()
+            .segments(2)
+            .max_bytesize(1024)
+            .build()
+            .into();
+
+        let key = b"token".to_vec();
+        cache
+            .set_with_expiration(key.clone(), vec![1], Duration::ZERO)
+            .unwrap();
+        assert_eq!(cache.get(&key), None);
+
+        let ttl = Duration::from_millis(50);
+        cache
+            .set_with_expiration(key.clone(), vec![1], ttl)
+            .unwrap();
+        assert_eq!(cache.get(&key), Some(vec![1]));
+        assert!(cache.ttl(&key).flatten().unwrap() <= ttl);
+
+        std::thread::sleep(ttl);
+        assert_eq!(cache.get(&key), None);
+        assert_eq!(cache.ttl(&key), None);
+    }
+
+    #[tokio::test]
+    async fn test_misbehaving_client() {
+        let cache = Cache::builder().segments(1).max_bytesize(64).build().into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, cache));
+
+        let mut socket = Socket::new(TcpStream::connect(addr).await.unwrap());
+
+        socket.send(Msg::Response(Response::Ok)).await.unwrap();
+        let msg = socket.recv().await.unwrap();
+        assert!(matches!(msg, Msg::Response(Response::Error(_))));
+
+        let set = Request::Set {
+            key: b"big".to_vec(),
+            value: vec![0; 100],
+            expiration: Expiration::Never,
+        };
+        socket.send(Msg::Request(set)).await.unwrap();
+        let msg = socket.recv().await.unwrap();
+        assert_eq!(msg, Msg::Response(Response::Error(TooBig.to_string())));
+
+        // an unknown message kind closes the connection
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[127; 13]).await.unwrap();
+        let mut socket = Socket::new(stream);
+        assert!(socket.recv().await.is_err());
+
+        // but the server is still alive
+        let mut socket = Socket::new(TcpStream::connect(addr).await.unwrap());
+        socket.send(Msg::Request(Request::Ping)).await.unwrap();
+        let msg = socket.recv().await.unwrap();
+        assert_eq!(msg, Msg::Response(Response::Pong));
+    }
+
+    #[test]
+    fn test_too_big() {
+        let cache: Cache = Cache::builder().segments(1).max_bytesize(64).build().into();
+
+        let key = b"key".to_vec();
+        cache.set(key.clone(), vec![1]).unwrap();
+        assert_eq!(cache.set(key.clone(), vec![0; 64]), Err(TooBig));
+        assert_eq!(cache.get(&key), None);
+
+        cache.set(b"b".to_vec(), vec![1]).unwrap();
+        let items = vec![
+            (b"a".to_vec(), vec![1], None),
+            (b"b".to_vec(), vec![2], None),
+            (b"c".to_vec(), vec![0; 64], None),
+        ];
+        assert_eq!(cache.set_many(items), Err(TooBig));
+        assert_eq!(
+            cache.get_many(&[&b"a"[..], b"b", b"c"]),
+            vec![None, Some(vec![1]), None]
+        );
+
+        let err = cache
+            .increment(vec![0; 64], 1, Some((0, None)))
+            .unwrap_err();
+        assert_eq!(err, CounterError::TooBig(TooBig));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let cache = Cache::builder().segments(1).max_bytesize(64).build().into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, signal) = tokio::sync::oneshot::channel::<()>();
+        let cfg = ServerCfg::builder()
+            .shutdown_timeout(Duration::from_secs(5))
+            .build();
+        let server = serve_with_shutdown(listener, cache, cfg, async {
+            let _ = signal.await;
+        });
+
+        let mut socket = Socket::new(TcpStream::connect(addr).await.unwrap());
+        socket.send(Msg::Request(Request::Ping)).await.unwrap();
+        let msg = socket.recv().await.unwrap();
+        assert_eq!(msg, Msg::Response(Response::Pong));
+
+        // the idle connection doesn't hold the shutdown back
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("shutdown in time")
+            .unwrap();
+
+        assert!(socket.recv().await.is_err());
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    async fn ping(socket: &mut Socket<TcpStream>) -> bool {
+        if socket.send(Msg::Request(Request::Ping)).await.is_err() {
+            return false;
+        }
+        let recv = tokio::time::timeout(Duration::from_millis(200), socket.recv()).await;
+        matches!(recv, Ok(Ok(Msg::Response(Response::Pong))))
+    }
+
+    async fn start(cfg: ServerCfg) -> std::net::SocketAddr {
+        let cache = Cache::builder().segments(1).max_bytesize(64).build().into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_with_cfg(listener, cache, cfg));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let cfg = ServerCfg::builder().max_connections(1).build();
+        let addr = start(cfg).await;
+
+        let mut first = Socket::new(TcpStream::connect(addr).await.unwrap());
+        assert!(ping(&mut first).await);
+        // queued in the backlog until the first connection is closed
+        let mut second = Socket::new(TcpStream::connect(addr).await.unwrap());
+        assert!(!ping(&mut second).await);
+        drop(first);
+        let recv = tokio::time::timeout(Duration::from_secs(1), second.recv()).await;
+        assert_eq!(recv.unwrap().unwrap(), Msg::Response(Response::Pong));
+
+        let cfg = ServerCfg::builder()
+            .max_connections(1)
+            .overflow(ConnectionOverflow::Refuse)
+            .build();
+        let addr = start(cfg).await;
+
+        let mut first = Socket::new(TcpStream::connect(addr).await.unwrap());
+        assert!(ping(&mut first).await);
+        let mut second = Socket::new(TcpStream::connect(addr).await.unwrap());
+        assert!(!ping(&mut second).await);
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut third = Socket::new(TcpStream::connect(addr).await.unwrap());
+        assert!(ping(&mut third).await);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        let cfg = ServerCfg::builder()
+            .idle_timeout(Duration::from_millis(300))
+            .read_timeout(Duration::from_millis(100))
+            .build();
+        let addr = start(cfg).await;
+
+        // requests keep the connection alive
+        let mut socket = Socket::new(TcpStream::connect(addr).await.unwrap());
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            assert!(ping(&mut socket).await);
+        }
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(!ping(&mut socket).await);
+
+        // a partial frame gets the read timeout, not the idle one
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[0, 0]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut socket = Socket::new(stream);
+        assert!(socket.recv().await.is_err());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_metrics() {
+        use tokio::io::AsyncReadExt;
+
+        async fn scrape(addr: std::net::SocketAddr, path: &str) -> String {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        let metrics_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let cfg = ServerCfg::builder().metrics_addr(metrics_addr).build();
+        let addr = start(cfg).await;
+
+        let mut socket = Socket::new(TcpStream::connect(addr).await.unwrap());
+        assert!(ping(&mut socket).await);
+        let set = Request::Set {
+            key: b"a".to_vec(),
+            value: vec![1],
+            expiration: Expiration::Never,
+        };
+        for request in [
+            set,
+            Request::Get(b"a".to_vec()),
+            Request::Get(b"b".to_vec()),
+        ] {
+            socket.send(Msg::Request(request)).await.unwrap();
+            socket.recv().await.unwrap();
+        }
+
+        let response = scrape(metrics_addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        for line in [
+            "memcrab_requests_total{kind=\"Ping\"} 1",
+            "memcrab_requests_total{kind=\"Get\"} 2",
+            "memcrab_request_duration_seconds_count{kind=\"Get\"} 2",
+            "memcrab_request_duration_seconds_bucket{kind=\"Set\",le=\"+Inf\"} 1",
+            "memcrab_hit_ratio 0.5",
+            "memcrab_evictions_total{segment=\"0\"} 0",
+            "memcrab_memory_used_bytes{segment=\"0\"} 2",
+            "memcrab_memory_max_bytes{segment=\"0\"} 64",
+            "memcrab_connections 1",
+        ] {
+            assert!(response.lines().any(|l| l == line), "missing {line}");
+        }
+        assert!(scrape(metrics_addr, "/").await.starts_with("HTTP/1.1 404"));
+    }
 }
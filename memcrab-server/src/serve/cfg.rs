@@ -0,0 +1,77 @@
+use crate::cache::Fsync;
+use memcrab_protocol::DEFAULT_MAX_PAYLOAD_LEN;
+use std::{path::PathBuf, time::Duration};
+use typed_builder::TypedBuilder;
+
+#[derive(TypedBuilder, Debug, Clone)]
+pub struct ServerCfg {
+    /// Requests with a bigger payload are answered with an error, then the connection is closed.
+    #[builder(default = DEFAULT_MAX_PAYLOAD_LEN)]
+    pub(super) max_payload_len: u64,
+    /// How long a shutdown waits for requests in flight, before the remaining connections are dropped.
+    #[builder(default = Duration::from_secs(10))]
+    pub(super) shutdown_timeout: Duration,
+    /// How many connections are served at once, unlimited by default.
+    #[builder(default, setter(into))]
+    pub(super) max_connections: Option<usize>,
+    /// What happens to connections beyond `max_connections`.
+    #[builder(default)]
+    pub(super) overflow: ConnectionOverflow,
+    /// A connection that doesn't start a new request for this long is closed.
+    /// Idle connections are kept forever by default.
+    #[builder(default, setter(into))]
+    pub(super) idle_timeout: Option<Duration>,
+    /// How long the rest of a request may take to arrive, once its first byte has been read.
+    /// A slower connection is closed.
+    #[builder(default = Some(Duration::from_secs(30)), setter(into))]
+    pub(super) read_timeout: Option<Duration>,
+    /// Where snapshots of the cache are kept. The server loads the snapshot when it starts,
+    /// if the file exists, and saves a new one on a `Save` request and when it shuts down.
+    #[builder(default, setter(into))]
+    pub(super) snapshot_path: Option<PathBuf>,
+    /// Also save a snapshot this often, if there is a `snapshot_path`.
+    #[builder(default, setter(into))]
+    pub(super) snapshot_interval: Option<Duration>,
+    /// Where the operation log is kept. Every change is appended to it, and the server replays it
+    /// when it starts, instead of loading the snapshot, so that a crash loses almost nothing.
+    /// If a change cannot be written to the log, the client gets an error, but the change
+    /// is already in the cache and seen by other clients, it's only lost on a restart.
+    #[builder(default, setter(into))]
+    pub(super) oplog_path: Option<PathBuf>,
+    /// How often the operation log is flushed to the disk.
+    #[builder(default)]
+    pub(super) oplog_fsync: Fsync,
+    /// Rewrite the operation log from the current state in the background, once it's at least
+    /// this many bytes long and twice as long as after the last rewrite.
+    #[builder(default = 64 * 1024 * 1024)]
+    pub(super) oplog_compaction_size: u64,
+    /// Replicate the primary server at this address: sync its whole cache, then apply its changes
+    /// as they happen. Writes are refused until the server is promoted with a `Promote` request.
+    #[builder(default, setter(into))]
+    pub(super) replica_of: Option<std::net::SocketAddr>,
+    /// Answer `Replicate` and `Promote` requests, they are refused by default. Any client that
+    /// can connect may then copy the whole cache or promote a replica, so only turn it on where
+    /// every client is trusted. A primary needs it to be replicated, a replica to be promoted.
+    #[builder(default)]
+    pub(super) allow_replication: bool,
+    /// Serve metrics in the Prometheus text format over HTTP on this address, at `/metrics`.
+    #[cfg(feature = "metrics")]
+    #[builder(default, setter(into))]
+    pub(super) metrics_addr: Option<std::net::SocketAddr>,
+}
+
+impl Default for ServerCfg {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// What happens to a new connection while `max_connections` are open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionOverflow {
+    /// Stop accepting until a connection is closed, new clients wait in the listener's backlog.
+    #[default]
+    Queue,
+    /// Accept the connection and close it at once.
+    Refuse,
+}
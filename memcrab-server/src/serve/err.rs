@@ -1,4 +1,4 @@
-use memcrab_protocol::Error as ProtocolError;
+use memcrab_protocol::{Error as ProtocolError, RequestId};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -6,6 +6,6 @@ pub enum ServerSideError {
     #[error("protocol error")]
     Protocol(#[from] ProtocolError),
 
-    #[error("invalid msg")]
-    InvalidMsg,
+    #[error("expected a request, got a response")]
+    InvalidMsg(RequestId),
 }
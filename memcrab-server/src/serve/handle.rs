@@ -0,0 +1,31 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::task::JoinHandle;
+
+/// A server running in the background, returned by [`serve_with_shutdown`](super::serve_with_shutdown).
+///
+/// Awaiting the handle waits until the server has shut down.
+#[derive(Debug)]
+pub struct ServerHandle {
+    inner: JoinHandle<io::Result<()>>,
+}
+
+impl ServerHandle {
+    pub(super) fn new(inner: JoinHandle<io::Result<()>>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Future for ServerHandle {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.inner)
+            .poll(cx)
+            .map(|joined| joined.unwrap_or_else(|err| Err(io::Error::other(err))))
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    io,
+    sync::{Arc, PoisonError, RwLock},
+};
+use tokio::sync::broadcast;
+
+use crate::cache::oplog::{OpLog, Written};
+
+// Batches of records a replica may fall behind by, before it has to sync again.
+const REPLICA_BACKLOG: usize = 16 * 1024;
+
+/// Records changes for the operation log and the replicas.
+/// Without either, nothing is recorded.
+pub(super) struct Journal {
+    oplog: Option<OpLog>,
+    // Changes share it, a new replica takes it alone to subscribe,
+    // so that every change is either in its full sync or in its stream.
+    gate: RwLock<()>,
+    replicas: broadcast::Sender<Arc<[u8]>>,
+}
+
+impl Journal {
+    pub fn new(oplog: Option<OpLog>) -> Self {
+        Self {
+            oplog,
+            gate: RwLock::new(()),
+            replicas: broadcast::Sender::new(REPLICA_BACKLOG),
+        }
+    }
+    pub fn oplog(&self) -> Option<&OpLog> {
+        self.oplog.as_ref()
+    }
+    /// Make a change with `change`, which sends its records to the [`Recorder`].
+    /// Completes once the records are in the operation log.
+    pub async fn record<T>(&self, change: impl FnOnce(&mut Recorder) -> T) -> (T, io::Result<()>) {
+        let (result, written) = {
+            let _gate = self.gate.read().unwrap_or_else(PoisonError::into_inner);
+            let mut recorder = Recorder {
+                journal: self,
+                on: self.oplog.is_some() || self.replicas.receiver_count() > 0,
+                written: vec![],
+            };
+            (change(&mut recorder), recorder.written)
+        };
+        for written in written {
+            let recorded = match written.await {
+                Ok(recorded) => recorded,
+                Err(_) => Err(io::Error::other("operation log has stopped")),
+            };
+            if recorded.is_err() {
+                return (result, recorded);
+            }
+        }
+        (result, Ok(()))
+    }
+    /// Records of the changes from now on.
+    /// The state of the cache that the replica needs first has to be read after this.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<[u8]>> {
+        let _gate = self.gate.write().unwrap_or_else(PoisonError::into_inner);
+        self.replicas.subscribe()
+    }
+}
+
+/// Takes the records of a change, if anybody listens.
+pub(super) struct Recorder<'a> {
+    journal: &'a Journal,
+    on: bool,
+    written: Vec<Written>,
+}
+
+impl Recorder<'_> {
+    /// Without listeners, the change doesn't need to make records.
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+    /// Records of a key have to be sent in the order of its changes,
+    /// e.g. under the lock of its segment.
+    pub fn send(&mut self, records: Vec<u8>) {
+        if !self.on || records.is_empty() {
+            return;
+        }
+        let records = Arc::<[u8]>::from(records);
+        // Fails only if every replica is gone meanwhile.
+        let _ = self.journal.replicas.send(records.clone());
+        if let Some(oplog) = &self.journal.oplog {
+            self.written.push(oplog.append(records));
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use memcrab_protocol::{RequestKind, SegmentStats, Stats};
+use std::{
+    fmt::Write,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+};
+use tracing::{info, warn};
+
+use super::server::ACCEPT_BACKOFF;
+
+// Upper bounds of the latency buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.1, 1.0,
+];
+
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request counts and latencies per [`RequestKind`].
+pub(super) struct Metrics {
+    // Indexed by the kind byte, request kinds are below 128.
+    requests: Vec<Latencies>,
+}
+
+#[derive(Default)]
+struct Latencies {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let requests = (0..128).map(|_| Latencies::default()).collect();
+        Self { requests }
+    }
+}
+
+impl Metrics {
+    pub fn observe(&self, kind: RequestKind, latency: Duration) {
+        let latencies = &self.requests[u8::from(kind) as usize];
+        let secs = latency.as_secs_f64();
+        // Buckets are stored non-cumulative, so that only one of them is touched.
+        if let Some(at) = BUCKETS.iter().position(|&le| secs <= le) {
+            latencies.buckets[at].fetch_add(1, Ordering::Relaxed);
+        }
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        latencies.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+        latencies.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The Prometheus text format of these metrics and `stats`.
+    pub fn render(&self, stats: &Stats) -> String {
+        let mut out = String::new();
+        self.render_requests(&mut out);
+        render_stats(&mut out, stats);
+        out
+    }
+
+    fn render_requests(&self, out: &mut String) {
+        let kinds = || {
+            (0..128u8).filter_map(|byte| {
+                let kind = RequestKind::try_from(byte).ok()?;
+                Some((kind, &self.requests[byte as usize]))
+            })
+        };
+        header(
+            out,
+            "memcrab_requests_total",
+            "counter",
+            "Requests handled.",
+        );
+        for (kind, latencies) in kinds() {
+            let count = latencies.count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "memcrab_requests_total{{kind=\"{kind:?}\"}} {count}");
+        }
+
+        let name = "memcrab_request_duration_seconds";
+        header(out, name, "histogram", "Time to handle a request.");
+        for (kind, latencies) in kinds() {
+            let mut cumulative = 0;
+            for (le, bucket) in BUCKETS.iter().zip(&latencies.buckets) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{kind=\"{kind:?}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let count = latencies.count.load(Ordering::Relaxed);
+            let sum = Duration::from_nanos(latencies.sum_nanos.load(Ordering::Relaxed));
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{kind=\"{kind:?}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(out, "{name}_sum{{kind=\"{kind:?}\"}} {}", sum.as_secs_f64());
+            let _ = writeln!(out, "{name}_count{{kind=\"{kind:?}\"}} {count}");
+        }
+    }
+}
+
+type Field = fn(&SegmentStats) -> u64;
+
+const SEGMENT_METRICS: [(&str, &str, &str, Field); 9] = [
+    (
+        "memcrab_hits_total",
+        "counter",
+        "Reads that found a value.",
+        |s| s.hits,
+    ),
+    (
+        "memcrab_misses_total",
+        "counter",
+        "Reads that found nothing.",
+        |s| s.misses,
+    ),
+    ("memcrab_sets_total", "counter", "Values stored.", |s| {
+        s.sets
+    }),
+    (
+        "memcrab_deletes_total",
+        "counter",
+        "Keys deleted on request.",
+        |s| s.deletes,
+    ),
+    (
+        "memcrab_evictions_total",
+        "counter",
+        "Values dropped to make room for others.",
+        |s| s.evictions,
+    ),
+    (
+        "memcrab_expirations_total",
+        "counter",
+        "Values dropped because their time to live ran out.",
+        |s| s.expirations,
+    ),
+    ("memcrab_items", "gauge", "Items stored.", |s| s.items),
+    (
+        "memcrab_memory_used_bytes",
+        "gauge",
+        "Bytes used by keys and values.",
+        |s| s.bytesize,
+    ),
+    (
+        "memcrab_memory_max_bytes",
+        "gauge",
+        "Bytes a segment may use.",
+        |s| s.max_bytesize,
+    ),
+];
+
+fn render_stats(out: &mut String, stats: &Stats) {
+    for (name, kind, help, field) in SEGMENT_METRICS {
+        header(out, name, kind, help);
+        for (at, segment) in stats.segments.iter().enumerate() {
+            let _ = writeln!(out, "{name}{{segment=\"{at}\"}} {}", field(segment));
+        }
+    }
+
+    let total = stats.total();
+    let reads = total.hits + total.misses;
+    let ratio = match reads {
+        0 => 0.0,
+        _ => total.hits as f64 / reads as f64,
+    };
+    header(
+        out,
+        "memcrab_hit_ratio",
+        "gauge",
+        "Share of reads that found a value.",
+    );
+    let _ = writeln!(out, "memcrab_hit_ratio {ratio}");
+
+    let server = [
+        (
+            "memcrab_connections",
+            "gauge",
+            "Connections open.",
+            stats.connections,
+        ),
+        (
+            "memcrab_connections_total",
+            "counter",
+            "Connections accepted.",
+            stats.total_connections,
+        ),
+        (
+            "memcrab_refused_connections_total",
+            "counter",
+            "Connections refused over the limit.",
+            stats.refused_connections,
+        ),
+    ];
+    for (name, kind, help, value) in server {
+        header(out, name, kind, help);
+        let _ = writeln!(out, "{name} {value}");
+    }
+    header(
+        out,
+        "memcrab_uptime_seconds",
+        "gauge",
+        "Time since the server has started.",
+    );
+    let _ = writeln!(out, "memcrab_uptime_seconds {}", stats.uptime.as_secs_f64());
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Answer `GET /metrics` with the output of `render`, until a shutdown.
+pub(super) async fn export<F>(listener: TcpListener, render: F, mut shutdown: watch::Receiver<bool>)
+where
+    F: Fn() -> String + Clone + Send + 'static,
+{
+    let mut scrapes = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            // The guard of `wait_for` isn't `Send`, drop it right away.
+            _ = async { shutdown.wait_for(|&stop| stop).await.map(drop) } => break,
+            Some(_) = scrapes.join_next() => continue,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!(error = %err, "cannot accept metrics connection");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            },
+        };
+        let render = render.clone();
+        scrapes.spawn(async move {
+            let scrape = tokio::time::timeout(HTTP_TIMEOUT, respond(stream, render));
+            if let Ok(Err(err)) = scrape.await {
+                info!(error = %err, "cannot answer metrics request");
+            }
+        });
+    }
+}
+
+// A minimal HTTP/1.1 exchange: one request per connection, its body is ignored.
+async fn respond(mut stream: TcpStream, render: impl Fn() -> String) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head is too big",
+            ));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    let (status, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", render()),
+        (Some(b"GET"), _) => ("404 Not Found", String::from("not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            String::from("method not allowed\n"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
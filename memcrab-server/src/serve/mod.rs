@@ -1,19 +1,56 @@
+mod cfg;
 mod err;
+mod handle;
+mod journal;
 mod listener;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod replication;
 mod server;
 mod socket;
 
-use std::io;
+use std::{future::Future, io};
 
 use crate::Cache;
 use err::ServerSideError;
 use memcrab_protocol::{AsyncRead, AsyncWrite};
 
+pub use cfg::{ConnectionOverflow, ServerCfg};
+pub use handle::ServerHandle;
 pub use listener::AcceptConnection;
 
 pub async fn serve<S>(listener: impl AcceptConnection<Stream = S>, cache: Cache) -> io::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    server::start_server(listener, cache).await
+    serve_with_cfg(listener, cache, ServerCfg::default()).await
+}
+
+pub async fn serve_with_cfg<S>(
+    listener: impl AcceptConnection<Stream = S>,
+    cache: Cache,
+    cfg: ServerCfg,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    server::start_server(listener, cache, cfg, std::future::pending()).await
+}
+
+/// Run the server in the background until `signal` completes.
+///
+/// Then the listener is closed, idle connections are closed,
+/// and requests in flight get [`ServerCfg`]'s shutdown timeout to finish.
+pub fn serve_with_shutdown<L, S>(
+    listener: L,
+    cache: Cache,
+    cfg: ServerCfg,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> ServerHandle
+where
+    L: AcceptConnection<Stream = S> + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let server = server::start_server(listener, cache, cfg, signal);
+    ServerHandle::new(tokio::spawn(server))
 }
@@ -0,0 +1,307 @@
+//! A replica sends [`Request::Replicate`] to its primary and gets the state of the cache in
+//! [`Response::Snapshot`] pieces, then every change in [`Response::Changes`] batches,
+//! in the formats of a snapshot and of the operation log.
+
+use memcrab_protocol::{
+    AsyncRead, AsyncWrite, Error as ProtocolError, Msg, Request, RequestId, Response, Socket,
+};
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    net::TcpStream,
+    sync::{
+        broadcast::error::{RecvError, TryRecvError},
+        mpsc, watch,
+    },
+};
+use tracing::{info, warn};
+
+use super::{server::State, socket::ServerSocket};
+use crate::cache::oplog;
+
+// Size of the pieces of a snapshot and of the batches of changes.
+const CHUNK_SIZE: usize = 1024 * 1024;
+// Pieces of a snapshot in flight between the cache and the connection.
+const CHUNKS_IN_FLIGHT: usize = 4;
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+enum ReplicationError {
+    #[error("io error")]
+    Io(#[from] io::Error),
+
+    #[error("protocol error")]
+    Protocol(#[from] ProtocolError),
+
+    #[error("primary refused to replicate: {0}")]
+    Refused(String),
+
+    #[error("unexpected message from the primary: {0:?}")]
+    Unexpected(Msg),
+}
+
+/// Send the state and then the changes of this server to a replica,
+/// until either side closes the connection or the server shuts down.
+pub(super) async fn serve_replica<S>(
+    socket: &mut ServerSocket<S>,
+    id: RequestId,
+    state: &Arc<State>,
+    shutdown: &mut watch::Receiver<bool>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Changes made from now on are streamed, so the snapshot must be taken after this.
+    let mut changes = state.journal.subscribe();
+    let (chunks, mut chunks_rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let dumping = state.clone();
+    // Stops with an error, once the receiver is dropped.
+    let dump = tokio::task::spawn_blocking(move || dumping.cache.dump(ChunkWriter::new(chunks)));
+    while let Some(chunk) = chunks_rx.recv().await {
+        if let Err(err) = socket.send(id, Response::Snapshot(chunk)).await {
+            warn!(error = ?err, "cannot send snapshot to replica, close connection");
+            return;
+        }
+    }
+    match dump
+        .await
+        .map_err(io::Error::from)
+        .and_then(|dumped| dumped)
+    {
+        Ok(items) => info!(items, "sent snapshot to replica"),
+        Err(err) => {
+            warn!(error = %err, "cannot dump cache for replica, close connection");
+            return;
+        }
+    }
+    // Ends the snapshot, even if nothing changes for a while.
+    if let Err(err) = socket.send(id, Response::Changes(vec![])).await {
+        warn!(error = ?err, "cannot send changes to replica, close connection");
+        return;
+    }
+
+    loop {
+        let received = tokio::select! {
+            _ = shutdown.wait_for(|&stop| stop) => {
+                info!("shutdown, close replication connection");
+                return;
+            }
+            // A replica only listens, so anything from it ends the stream.
+            ready = socket.ready() => {
+                match ready {
+                    Ok(false) => info!("replica has closed the connection"),
+                    Ok(true) => warn!("unexpected request from replica, close connection"),
+                    Err(err) => warn!(error = %err, "cannot receive from replica, close connection"),
+                }
+                return;
+            }
+            received = changes.recv() => received,
+        };
+        let mut batch = match received {
+            Ok(records) => records.to_vec(),
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(
+                    skipped,
+                    "replica has fallen behind, close connection to sync again"
+                );
+                return;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        while batch.len() < CHUNK_SIZE {
+            match changes.try_recv() {
+                Ok(records) => batch.extend_from_slice(&records),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    warn!(
+                        skipped,
+                        "replica has fallen behind, close connection to sync again"
+                    );
+                    return;
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+        // Cut like the snapshot, a record may go on in the next batch.
+        for batch in batch.chunks(CHUNK_SIZE) {
+            if let Err(err) = socket.send(id, Response::Changes(batch.to_vec())).await {
+                warn!(error = ?err, "cannot send changes to replica, close connection");
+                return;
+            }
+        }
+    }
+}
+
+/// Follow the primary at `primary` until the task is aborted by a promotion.
+/// Every connection starts with a full sync, a lost one is made again after a backoff.
+pub(super) async fn replicate(state: Arc<State>, primary: SocketAddr) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match follow(&state, primary, &mut backoff).await {
+            Ok(()) => info!(%primary, "primary has closed replication, reconnect"),
+            Err(err) => warn!(%primary, error = ?err, "replication failed, reconnect"),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Resets `backoff` once the replica is in sync.
+async fn follow(
+    state: &Arc<State>,
+    primary: SocketAddr,
+    backoff: &mut Duration,
+) -> Result<(), ReplicationError> {
+    let stream = TcpStream::connect(primary).await?;
+    // Pieces of a snapshot and batches of changes are cut at the size of a chunk,
+    // however big the items in them are.
+    let mut socket = Socket::new(stream).with_max_payload_len(CHUNK_SIZE as u64);
+    socket.send(Msg::Request(Request::Replicate)).await?;
+    info!(%primary, "replicating");
+
+    let (chunks, chunks_rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let mut chunks = Some(chunks);
+    let restoring = state.clone();
+    let mut restore = Some(tokio::task::spawn_blocking(move || {
+        restoring.cache.clear();
+        restoring.cache.restore(ChunkReader::new(chunks_rx))
+    }));
+    // The start of a record that goes on in the next batch.
+    let mut cut = vec![];
+    loop {
+        let msg = match socket.recv().await {
+            Ok(msg) => msg,
+            Err(ProtocolError::IO(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(())
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match (msg, &chunks) {
+            (Msg::Response(Response::Snapshot(chunk)), Some(sender)) => {
+                // Fails only if the restore has stopped, its error is more telling.
+                if sender.send(chunk).await.is_err() {
+                    chunks = None;
+                    let restore = restore.take().expect("restore is running");
+                    restore.await.map_err(io::Error::from)??;
+                }
+            }
+            (Msg::Response(Response::Changes(batch)), _) => {
+                // The first batch ends the snapshot.
+                if let Some(restore) = restore.take() {
+                    chunks = None;
+                    let items = restore.await.map_err(io::Error::from)??;
+                    info!(items, "synced with primary");
+                    *backoff = MIN_BACKOFF;
+                    rewrite_oplog(state).await?;
+                }
+                let (applied, recorded) = state
+                    .journal
+                    .record(|recorder| {
+                        let mut records = mem::take(&mut cut);
+                        records.extend_from_slice(&batch);
+                        let applied = oplog::apply(&records, &state.cache)?;
+                        cut = records.split_off(applied);
+                        recorder.send(records);
+                        io::Result::Ok(())
+                    })
+                    .await;
+                applied?;
+                if let Err(err) = recorded {
+                    warn!(error = %err, "cannot write operation log");
+                }
+            }
+            (Msg::Response(Response::Error(msg)), _) => return Err(ReplicationError::Refused(msg)),
+            (msg, _) => return Err(ReplicationError::Unexpected(msg)),
+        }
+    }
+}
+
+// The cache has been replaced by a full sync behind the back of the operation log.
+async fn rewrite_oplog(state: &Arc<State>) -> io::Result<()> {
+    if state.journal.oplog().is_none() {
+        return Ok(());
+    }
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        let oplog = state.journal.oplog().expect("replica has an operation log");
+        // A compaction that is running now may have read the cache before the sync.
+        while !oplog.compact(&state.cache)? {
+            std::thread::sleep(MIN_BACKOFF);
+        }
+        Ok(())
+    })
+    .await?
+}
+
+// Cuts what is written into pieces for a connection.
+struct ChunkWriter {
+    chunks: mpsc::Sender<Vec<u8>>,
+    chunk: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn new(chunks: mpsc::Sender<Vec<u8>>) -> Self {
+        Self {
+            chunks,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    // Pieces are cut at their size exactly, an item may go on in the next one.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        if self.chunk.len() == CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+        self.chunks
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+// Reads the pieces from a connection in a row, ends when the sender is dropped.
+struct ChunkReader {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    at: usize,
+}
+
+impl ChunkReader {
+    fn new(chunks: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            chunks,
+            chunk: vec![],
+            at: 0,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at == self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => (self.chunk, self.at) = (chunk, 0),
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.at);
+        buf[..n].copy_from_slice(&self.chunk[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
@@ -1,53 +1,561 @@
-use core::panic;
-use memcrab_protocol::{AsyncRead, AsyncWrite, Error as ProtocolError, Request, Response};
-use std::{io, num::NonZeroU32, sync::Arc};
-use tracing::info;
+use memcrab_protocol::{
+    AsyncRead, AsyncWrite, Error as ProtocolError, ParseError, Request, Response, SegmentStats,
+    Stats,
+};
+use std::{
+    error::Error,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{watch, Mutex},
+    task::{AbortHandle, JoinHandle, JoinSet},
+};
+use tracing::{info, warn};
 
-use super::{listener::AcceptConnection, socket::ServerSocket};
-use crate::{cache::Cache, serve::err::ServerSideError};
+use super::{journal::Journal, listener::AcceptConnection, replication, socket::ServerSocket};
+use crate::{
+    cache::{
+        oplog::{self, Change, OpLog},
+        AppendError, Cache, CasOutcome, CounterError, TooBig,
+    },
+    serve::{err::ServerSideError, ConnectionOverflow, ServerCfg},
+};
+
+pub(super) const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+const OPLOG_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// Everything that the connections of a server share.
+pub(super) struct State {
+    pub(super) cache: Cache,
+    pub(super) cfg: ServerCfg,
+    pub(super) journal: Journal,
+    // Set while the server replicates a primary.
+    read_only: AtomicBool,
+    replication: std::sync::Mutex<Option<AbortHandle>>,
+    started: Instant,
+    connections: AtomicU64,
+    total_connections: AtomicU64,
+    refused_connections: AtomicU64,
+    // Held while a snapshot is written, so that two saves don't write the same file.
+    saving: Mutex<()>,
+    #[cfg(feature = "metrics")]
+    metrics: super::metrics::Metrics,
+}
+
+impl State {
+    fn stats(&self) -> Stats {
+        let segments = self.cache.stats().into_iter().map(|segment| SegmentStats {
+            hits: segment.hits(),
+            misses: segment.misses(),
+            sets: segment.sets(),
+            deletes: segment.deletes(),
+            evictions: segment.evictions(),
+            expirations: segment.expirations(),
+            items: segment.items() as u64,
+            bytesize: segment.bytesize() as u64,
+            max_bytesize: segment.max_bytesize() as u64,
+        });
+        Stats {
+            uptime: self.started.elapsed(),
+            connections: self.connections.load(Ordering::Relaxed),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            refused_connections: self.refused_connections.load(Ordering::Relaxed),
+            segments: segments.collect(),
+        }
+    }
+    // Stop replicating and accept changes. Changes already received are kept.
+    fn promote(&self) {
+        let replication = self
+            .replication
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(replication) = replication {
+            replication.abort();
+            info!("promoted to primary");
+        }
+        self.read_only.store(false, Ordering::Relaxed);
+    }
+}
+
+// Counts a connection as open for as long as it lives, even if its task is aborted.
+struct OpenConnection(Arc<State>);
+
+impl OpenConnection {
+    fn new(state: Arc<State>) -> Self {
+        state.connections.fetch_add(1, Ordering::Relaxed);
+        state.total_connections.fetch_add(1, Ordering::Relaxed);
+        Self(state)
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
 
 pub(super) async fn start_server<S>(
     listener: impl AcceptConnection<Stream = S>,
     cache: Cache,
+    cfg: ServerCfg,
+    signal: impl Future<Output = ()>,
 ) -> io::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     info!("memcrab server started...");
-    let cache = Arc::new(cache);
+    let max_connections = cfg.max_connections.unwrap_or(usize::MAX);
+    let overflow = cfg.overflow;
+    let (cache, cfg, oplog) = tokio::task::spawn_blocking(move || {
+        let oplog = recover(&cache, &cfg)?;
+        Ok::<_, io::Error>((cache, cfg, oplog))
+    })
+    .await??;
+    let replica_of = cfg.replica_of;
+    let state = Arc::new(State {
+        cache,
+        cfg,
+        journal: Journal::new(oplog),
+        read_only: AtomicBool::new(replica_of.is_some()),
+        replication: Default::default(),
+        started: Instant::now(),
+        connections: AtomicU64::new(0),
+        total_connections: AtomicU64::new(0),
+        refused_connections: AtomicU64::new(0),
+        saving: Mutex::new(()),
+        #[cfg(feature = "metrics")]
+        metrics: Default::default(),
+    });
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    #[cfg(feature = "metrics")]
+    let _exporter = export_metrics(&state, shutdown_rx.clone()).await?;
+    let _sweeper = sweep_expired(&state, shutdown_rx.clone());
+    let _saver = save_periodically(&state, shutdown_rx.clone());
+    let mut maintainer = maintain_oplog(&state, shutdown_rx.clone());
+    let mut replication = JoinSet::new();
+    if let Some(primary) = replica_of {
+        let task = replication.spawn(replication::replicate(state.clone(), primary));
+        *state
+            .replication
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(task);
+    }
+    let mut connections = JoinSet::new();
+    tokio::pin!(signal);
     loop {
-        let stream = listener.accept_connection().await?;
-        let socket = ServerSocket::from_stream(stream);
-        let cache = cache.clone();
-        tokio::spawn(async move { handle(socket, cache).await });
+        // Queued clients wait in the listener's backlog, until a connection is closed.
+        let full = connections.len() >= max_connections;
+        let accepting = !full || overflow == ConnectionOverflow::Refuse;
+        let accepted = tokio::select! {
+            _ = &mut signal => break,
+            // Reap finished connections, so that the set doesn't grow forever.
+            Some(_) = connections.join_next() => continue,
+            accepted = listener.accept_connection(), if accepting => accepted,
+        };
+        let stream = match accepted {
+            Ok(stream) => stream,
+            // E.g. out of file descriptors, give the open connections a moment to finish.
+            Err(err) => {
+                warn!(error = %err, "cannot accept connection");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        if full {
+            state.refused_connections.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                connections = connections.len(),
+                "too many connections, refuse connection"
+            );
+            continue;
+        }
+        let socket = ServerSocket::from_stream(stream, &state.cfg);
+        let connection = OpenConnection::new(state.clone());
+        connections.spawn(handle(socket, connection, shutdown_rx.clone()));
+        info!(connections = connections.len(), "accepted connection");
+        if connections.len() == max_connections && overflow == ConnectionOverflow::Queue {
+            info!(
+                connections = connections.len(),
+                "connection limit reached, queue new connections"
+            );
+        }
+    }
+
+    info!(connections = connections.len(), "shutting down");
+    drop(listener);
+    let _ = shutdown.send(true);
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if tokio::time::timeout(state.cfg.shutdown_timeout, drain)
+        .await
+        .is_err()
+    {
+        warn!(
+            connections = connections.len(),
+            "shutdown timeout passed, drop connections"
+        );
+        connections.shutdown().await;
     }
+    replication.shutdown().await;
+    // A compaction must not rewrite the log behind the final flush.
+    while maintainer.join_next().await.is_some() {}
+    if state.journal.oplog().is_some() {
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || {
+            let oplog = state.journal.oplog().expect("server has an operation log");
+            oplog.sync()
+        })
+        .await??;
+    }
+    if state.cfg.snapshot_path.is_some() {
+        save_snapshot(&state).await?;
+    }
+    info!("memcrab server stopped");
+    Ok(())
 }
 
-async fn handle<S>(mut socket: ServerSocket<S>, cache: Arc<Cache>)
-where
+// The operation log is newer than the snapshot, if there is one.
+// Without a log, the snapshot is loaded and a new log starts from it.
+fn recover(cache: &Cache, cfg: &ServerCfg) -> io::Result<Option<OpLog>> {
+    let oplog_exists = cfg.oplog_path.as_ref().is_some_and(|path| path.exists());
+    if let (Some(path), false) = (&cfg.snapshot_path, oplog_exists) {
+        let started = Instant::now();
+        match cache.load(path) {
+            Ok(items) => info!(items, elapsed = ?started.elapsed(), "loaded snapshot"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => info!("no snapshot, start empty"),
+            Err(err) => {
+                warn!(error = %err, "cannot load snapshot");
+                return Err(err);
+            }
+        }
+    }
+    let Some(path) = &cfg.oplog_path else {
+        return Ok(None);
+    };
+    let oplog = OpLog::open(path, cfg.oplog_fsync, cfg.oplog_compaction_size, cache)
+        .inspect_err(|err| warn!(error = %err, "cannot open operation log"))?;
+    Ok(Some(oplog))
+}
+
+async fn save_snapshot(state: &Arc<State>) -> io::Result<usize> {
+    let Some(path) = state.cfg.snapshot_path.clone() else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "snapshots are not configured",
+        ));
+    };
+    let _saving = state.saving.lock().await;
+    let started = Instant::now();
+    let cache = state.clone();
+    match tokio::task::spawn_blocking(move || cache.cache.save(&path)).await? {
+        Ok(items) => {
+            info!(items, elapsed = ?started.elapsed(), "saved snapshot");
+            Ok(items)
+        }
+        Err(err) => {
+            warn!(error = %err, "cannot save snapshot");
+            Err(err)
+        }
+    }
+}
+
+// Aborted when dropped with the server.
+fn sweep_expired(state: &Arc<State>, mut shutdown: watch::Receiver<bool>) -> JoinSet<()> {
+    let mut sweeper = JoinSet::new();
+    if let Some(sweep) = state.cache.sweeper() {
+        sweeper.spawn(async move {
+            tokio::select! {
+                _ = shutdown.wait_for(|&stop| stop) => {}
+                _ = sweep => {}
+            }
+        });
+    }
+    sweeper
+}
+
+// Aborted when dropped with the server, the last snapshot is saved on shutdown anyway.
+fn save_periodically(state: &Arc<State>, mut shutdown: watch::Receiver<bool>) -> JoinSet<()> {
+    let mut saver = JoinSet::new();
+    if let (Some(_), Some(interval)) = (&state.cfg.snapshot_path, state.cfg.snapshot_interval) {
+        let state = state.clone();
+        saver.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = async { shutdown.wait_for(|&stop| stop).await.map(drop) } => return,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                // A failure is logged, the next attempt may succeed.
+                let _ = save_snapshot(&state).await;
+            }
+        });
+    }
+    saver
+}
+
+// Aborted when dropped with the server, even if it doesn't get to shut down.
+#[cfg(feature = "metrics")]
+async fn export_metrics(
+    state: &Arc<State>,
+    shutdown: watch::Receiver<bool>,
+) -> io::Result<JoinSet<()>> {
+    let mut exporter = JoinSet::new();
+    if let Some(addr) = state.cfg.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        info!(addr = %listener.local_addr()?, "serving metrics");
+        let state = state.clone();
+        let render = move || state.metrics.render(&state.stats());
+        exporter.spawn(super::metrics::export(listener, render, shutdown));
+    }
+    Ok(exporter)
+}
+
+// Flushes the operation log every second and compacts it when it has grown.
+// Finishes a running compaction before it stops on shutdown.
+fn maintain_oplog(state: &Arc<State>, mut shutdown: watch::Receiver<bool>) -> JoinSet<()> {
+    let mut maintainer = JoinSet::new();
+    if state.journal.oplog().is_none() {
+        return maintainer;
+    }
+    let state = state.clone();
+    maintainer.spawn(async move {
+        let mut compaction = None;
+        loop {
+            tokio::select! {
+                _ = async { shutdown.wait_for(|&stop| stop).await.map(drop) } => break,
+                _ = tokio::time::sleep(OPLOG_SYNC_INTERVAL) => {}
+            }
+            if compaction.as_ref().is_some_and(JoinHandle::is_finished) {
+                compacted(compaction.take()).await;
+            }
+            let oplog = state.journal.oplog().expect("server has an operation log");
+            if compaction.is_none() && oplog.needs_compaction() {
+                // Runs next to the flushes, a failure is tried again later.
+                let state = state.clone();
+                compaction = Some(tokio::task::spawn_blocking(move || {
+                    let oplog = state.journal.oplog().expect("server has an operation log");
+                    oplog.compact(&state.cache)
+                }));
+            }
+            let state = state.clone();
+            let synced = tokio::task::spawn_blocking(move || {
+                let oplog = state.journal.oplog().expect("server has an operation log");
+                oplog.sync()
+            });
+            if let Ok(Err(err)) = synced.await {
+                warn!(error = %err, "cannot flush operation log");
+            }
+        }
+        compacted(compaction).await;
+    });
+    maintainer
+}
+
+// Waits for a compaction to end, if there is one, and logs a failure.
+async fn compacted(compaction: Option<JoinHandle<io::Result<bool>>>) {
+    let Some(compaction) = compaction else {
+        return;
+    };
+    match compaction.await {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => warn!(error = %err, "cannot compact operation log"),
+        Err(err) => warn!(error = %err, "operation log compaction panicked"),
+    }
+}
+
+async fn handle<S>(
+    mut socket: ServerSocket<S>,
+    connection: OpenConnection,
+    mut shutdown: watch::Receiver<bool>,
+) where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    let cache = cache.as_ref();
+    let state = &connection.0;
+    let cfg = &state.cfg;
     loop {
-        let request = match socket.recv().await {
-            Ok(req) => req,
-            Err(ServerSideError::Protocol(ProtocolError::IO(err))) => match err.kind() {
-                io::ErrorKind::UnexpectedEof => {
-                    info!("eof, close connection");
-                    return;
-                }
-                _ => panic!("{:?}", err),
-            },
-            err => panic!("{:?}", err),
+        // A connection waiting for its next request is idle and can be closed at once,
+        // a request that has started to arrive is always answered.
+        let ready = tokio::select! {
+            _ = shutdown.wait_for(|&stop| stop) => {
+                info!("shutdown, close connection");
+                return;
+            }
+            ready = timeout(cfg.idle_timeout, socket.ready()) => ready,
+        };
+        match ready {
+            Some(Ok(true)) => {}
+            Some(Ok(false)) => {
+                info!("eof, close connection");
+                return;
+            }
+            Some(Err(err)) => {
+                warn!(error = %err, "cannot receive request, close connection");
+                return;
+            }
+            None => {
+                info!("idle timeout passed, close connection");
+                return;
+            }
+        }
+        let Some(received) = timeout(cfg.read_timeout, socket.recv()).await else {
+            warn!("read timeout passed, close connection");
+            return;
+        };
+        let (id, response) = match received {
+            // A replica is served on this connection until it's closed.
+            Ok((id, Request::Replicate))
+                if state.cfg.allow_replication && !state.read_only.load(Ordering::Relaxed) =>
+            {
+                info!(id, "replica connected");
+                replication::serve_replica(&mut socket, id, state, &mut shutdown).await;
+                return;
+            }
+            Ok((id, request)) => {
+                info!("received request {}: {:?}", id, &request);
+                #[cfg(feature = "metrics")]
+                let (kind, started) = (request.kind(), Instant::now());
+                let response = response_to(request, state).await;
+                #[cfg(feature = "metrics")]
+                state.metrics.observe(kind, started.elapsed());
+                (id, response)
+            }
+            // The whole frame was read, so the next one can still be handled.
+            Err(err @ ServerSideError::InvalidMsg(id)) => {
+                warn!(id, error = %err, "invalid request");
+                (id, error_response(err))
+            }
+            Err(ServerSideError::Protocol(ProtocolError::IO(err)))
+                if err.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                info!("eof, close connection");
+                return;
+            }
+            // The payload is left unread, tell the client about the limit before closing.
+            Err(ServerSideError::Protocol(ProtocolError::Parse(
+                err @ ParseError::TooBig { id, .. },
+            ))) => {
+                warn!(id, error = %err, "request is too big, close connection");
+                let _ = socket.send(id, error_response(err)).await;
+                return;
+            }
+            // Where the next frame starts is unknown, the connection can't go on.
+            Err(err) => {
+                warn!(error = ?err, "cannot receive request, close connection");
+                return;
+            }
         };
-        info!("received request: {:?}", &request);
-        let response = response_to(request, cache);
-        info!("sending response: {:?}", &response);
-        socket.send(response).await.unwrap();
+        info!("sending response {}: {:?}", id, &response);
+        if let Err(err) = socket.send(id, response).await {
+            warn!(id, error = ?err, "cannot send response, close connection");
+            return;
+        }
     }
 }
 
-fn response_to(request: Request, cache: &Cache) -> Response {
+/// Like [`tokio::time::timeout`], but `None` on timeout and no limit without a duration.
+async fn timeout<F: Future>(duration: Option<Duration>, future: F) -> Option<F::Output> {
+    match duration {
+        Some(duration) => tokio::time::timeout(duration, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+async fn response_to(request: Request, state: &Arc<State>) -> Response {
+    if matches!(request, Request::Replicate | Request::Promote) && !state.cfg.allow_replication {
+        return Response::Error(String::from(
+            "replication requests are not allowed on this server",
+        ));
+    }
+    if let Request::Save = request {
+        return ok_response(save_snapshot(state).await.map(drop));
+    }
+    if let Request::Replicate = request {
+        // A primary turns the connection into a stream before it gets here.
+        return Response::Error(String::from("a replica cannot be replicated"));
+    }
+    let Some(change) = change_of(&request) else {
+        return apply(request, state);
+    };
+    if state.read_only.load(Ordering::Relaxed) {
+        return Response::Error(String::from("a replica is read-only, write to the primary"));
+    }
+    let cache = &state.cache;
+    let (response, recorded) = state
+        .journal
+        .record(|recorder| match change {
+            _ if !recorder.is_on() => apply(request, state),
+            Change::Clear => {
+                oplog::clear(cache, |records| recorder.send(records));
+                Response::Ok
+            }
+            Change::Keys(keys) => {
+                let response = apply(request, state);
+                if changed(&response) {
+                    oplog::encode(cache, &keys, |records| recorder.send(records));
+                }
+                response
+            }
+        })
+        .await;
+    match recorded {
+        Ok(()) => response,
+        Err(err) => {
+            warn!(error = %err, "cannot write operation log");
+            Response::Error(format!("change is applied, but not logged: {err}"))
+        }
+    }
+}
+
+/// What `request` may change, `None` if it only reads.
+fn change_of(request: &Request) -> Option<Change> {
+    let key = |key: &Vec<u8>| Some(Change::Keys(vec![key.clone()]));
+    match request {
+        Request::Set { key: k, .. }
+        | Request::Cas { key: k, .. }
+        | Request::Increment { key: k, .. }
+        | Request::Decrement { key: k, .. }
+        | Request::Add { key: k, .. }
+        | Request::Replace { key: k, .. }
+        | Request::Append { key: k, .. }
+        | Request::Prepend { key: k, .. }
+        | Request::Touch { key: k, .. }
+        | Request::GetAndTouch { key: k, .. } => key(k),
+        Request::Delete(k) | Request::Persist(k) => key(k),
+        Request::SetMany(items) => Some(Change::Keys(
+            items.iter().map(|item| item.key.clone()).collect(),
+        )),
+        Request::DeleteMany(keys) => Some(Change::Keys(keys.clone())),
+        Request::Clear => Some(Change::Clear),
+        Request::Get(_)
+        | Request::GetMany(_)
+        | Request::GetVersioned(_)
+        | Request::Ttl(_)
+        | Request::Ping
+        | Request::Stats
+        | Request::Save
+        | Request::Replicate
+        | Request::Promote => None,
+    }
+}
+
+// Nothing has changed if a request that may change something is answered with one of these.
+fn changed(response: &Response) -> bool {
+    !matches!(
+        response,
+        Response::KeyNotFound
+            | Response::NotStored
+            | Response::NotCounter
+            | Response::VersionMismatch
+    )
+}
+
+fn apply(request: Request, state: &State) -> Response {
+    let cache = &state.cache;
     match request {
         Request::Ping => Response::Pong,
         Request::Get(ref key) => match cache.get(key) {
@@ -63,17 +571,144 @@ fn response_to(request: Request, cache: &Cache) -> Response {
             value,
             expiration,
         } => {
-            if expiration == 0 {
-                cache.set(key, value)
-            } else {
-                let exp = NonZeroU32::new(expiration).unwrap();
-                cache.set_with_expiration(key, value, exp);
-            }
-            Response::Ok
+            let result = match expiration.ttl() {
+                Some(ttl) => cache.set_with_expiration(key, value, ttl),
+                None => cache.set(key, value),
+            };
+            ok_response(result)
         }
         Request::Clear => {
             cache.clear();
             Response::Ok
         }
+        Request::GetMany(keys) => Response::Values(cache.get_many(&keys)),
+        Request::SetMany(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| (item.key, item.value, item.expiration.ttl()))
+                .collect();
+            ok_response(cache.set_many(items))
+        }
+        Request::DeleteMany(keys) => {
+            let removed = cache.remove_many(&keys);
+            Response::Deleted(removed.iter().map(Option::is_some).collect())
+        }
+        Request::GetVersioned(ref key) => match cache.get_with_version(key) {
+            Some((value, version)) => Response::VersionedValue { value, version },
+            None => Response::KeyNotFound,
+        },
+        Request::Cas {
+            key,
+            value,
+            expiration,
+            version,
+        } => {
+            let exp = expiration.ttl();
+            match cache.compare_and_swap(key, value, exp, version) {
+                Ok(CasOutcome::Stored) => Response::Ok,
+                Ok(CasOutcome::VersionMismatch) => Response::VersionMismatch,
+                Ok(CasOutcome::NotFound) => Response::KeyNotFound,
+                Err(err) => error_response(err),
+            }
+        }
+        Request::Increment {
+            key,
+            delta,
+            initial,
+            expiration,
+        } => {
+            let init = initial.map(|n| (n, expiration.ttl()));
+            counter_response(cache.increment(key, delta, init))
+        }
+        Request::Decrement {
+            key,
+            delta,
+            initial,
+            expiration,
+        } => {
+            let init = initial.map(|n| (n, expiration.ttl()));
+            counter_response(cache.decrement(key, delta, init))
+        }
+        Request::Add {
+            key,
+            value,
+            expiration,
+        } => stored_response(cache.add(key, value, expiration.ttl())),
+        Request::Replace {
+            key,
+            value,
+            expiration,
+        } => stored_response(cache.replace(key, value, expiration.ttl())),
+        Request::Ttl(ref key) => match cache.ttl(key) {
+            Some(ttl) => Response::Ttl(ttl),
+            None => Response::KeyNotFound,
+        },
+        Request::Touch {
+            ref key,
+            expiration,
+        } => found_response(cache.touch(key, expiration.ttl())),
+        Request::Persist(ref key) => found_response(cache.persist(key)),
+        Request::GetAndTouch {
+            ref key,
+            expiration,
+        } => match cache.get_and_touch(key, expiration.ttl()) {
+            Some(val) => Response::Value(val),
+            None => Response::KeyNotFound,
+        },
+        Request::Append { key, value } => append_response(cache.append(&key, &value)),
+        Request::Prepend { key, value } => append_response(cache.prepend(&key, &value)),
+        Request::Stats => Response::Stats(state.stats()),
+        Request::Promote => {
+            state.promote();
+            Response::Ok
+        }
+        // Answered by `response_to` before they get here, a request must never panic anyway.
+        Request::Save | Request::Replicate => {
+            Response::Error(String::from("request is not applied to the cache"))
+        }
+    }
+}
+
+fn counter_response(result: Result<u64, CounterError>) -> Response {
+    match result {
+        Ok(n) => Response::Counter(n),
+        Err(CounterError::NotFound) => Response::KeyNotFound,
+        Err(CounterError::NotCounter) => Response::NotCounter,
+        Err(err @ CounterError::TooBig(_)) => error_response(err),
+    }
+}
+
+fn stored_response(result: Result<bool, TooBig>) -> Response {
+    match result {
+        Ok(true) => Response::Ok,
+        Ok(false) => Response::NotStored,
+        Err(err) => error_response(err),
+    }
+}
+
+fn ok_response(result: Result<(), impl Error>) -> Response {
+    match result {
+        Ok(()) => Response::Ok,
+        Err(err) => error_response(err),
+    }
+}
+
+fn error_response(err: impl Error) -> Response {
+    Response::Error(err.to_string())
+}
+
+fn append_response(result: Result<(), AppendError>) -> Response {
+    match result {
+        Ok(()) => Response::Ok,
+        Err(AppendError::NotFound) => Response::NotStored,
+        Err(err @ AppendError::TooBig) => error_response(err),
+    }
+}
+
+fn found_response(found: bool) -> Response {
+    if found {
+        Response::Ok
+    } else {
+        Response::KeyNotFound
     }
 }
@@ -1,22 +1,29 @@
-use super::ServerSideError;
-use memcrab_protocol::{AsyncRead, AsyncWrite, Msg, Request, Response, Socket};
+use super::{ServerCfg, ServerSideError};
+use memcrab_protocol::{AsyncRead, AsyncWrite, Msg, Request, RequestId, Response, Socket};
+use std::io;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ServerSocket<S> {
-    inner: Socket<S>,
+    inner: Socket<BufReader<S>>,
 }
 
 impl<S> ServerSocket<S> {
-    pub fn new(inner: Socket<S>) -> Self {
+    pub fn new(inner: Socket<BufReader<S>>) -> Self {
         Self { inner }
     }
-    pub fn from_stream(stream: S) -> Self {
-        Socket::new(stream).into()
+}
+
+impl<S: AsyncRead> ServerSocket<S> {
+    pub fn from_stream(stream: S, cfg: &ServerCfg) -> Self {
+        Socket::new(BufReader::new(stream))
+            .with_max_payload_len(cfg.max_payload_len)
+            .into()
     }
 }
 
-impl<S> From<Socket<S>> for ServerSocket<S> {
-    fn from(inner: Socket<S>) -> Self {
+impl<S> From<Socket<BufReader<S>>> for ServerSocket<S> {
+    fn from(inner: Socket<BufReader<S>>) -> Self {
         Self::new(inner)
     }
 }
@@ -25,15 +32,21 @@ impl<S> ServerSocket<S>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    pub async fn recv(&mut self) -> Result<Request, ServerSideError> {
-        let msg = self.inner.recv().await?;
+    /// Wait for the first byte of the next request, without consuming it.
+    /// Returns `false` if the client has closed the connection instead.
+    pub async fn ready(&mut self) -> io::Result<bool> {
+        let buf = self.inner.get_mut().fill_buf().await?;
+        Ok(!buf.is_empty())
+    }
+    pub async fn recv(&mut self) -> Result<(RequestId, Request), ServerSideError> {
+        let (id, msg) = self.inner.recv_with_id().await?;
         match msg {
-            Msg::Request(req) => Ok(req),
-            Msg::Response(_) => Err(ServerSideError::InvalidMsg),
+            Msg::Request(req) => Ok((id, req)),
+            Msg::Response(_) => Err(ServerSideError::InvalidMsg(id)),
         }
     }
-    pub async fn send(&mut self, response: Response) -> Result<(), ServerSideError> {
-        self.inner.send(Msg::Response(response)).await?;
+    pub async fn send(&mut self, id: RequestId, response: Response) -> Result<(), ServerSideError> {
+        self.inner.send_with_id(id, Msg::Response(response)).await?;
         Ok(())
     }
 }
@@ -0,0 +1,456 @@
+mod ring;
+
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinSet;
+
+use crate::{
+    connections::{ConnectionCfg, Multiplexed},
+    forward::key_requests,
+    Error, RawClient,
+};
+pub(crate) use ring::Ring;
+
+// Keeps the ring small, a node gets 160 points on it per unit of weight.
+const MAX_WEIGHT: u32 = 1000;
+
+/// Where a node of a [`Cluster`] listens.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(target_family = "unix")]
+    Unix(std::path::PathBuf),
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl From<std::path::PathBuf> for Endpoint {
+    fn from(path: std::path::PathBuf) -> Self {
+        Self::Unix(path)
+    }
+}
+
+// Also the name that places the node on the ring.
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(target_family = "unix")]
+            Self::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Nodes and settings of a [`Cluster`].
+#[derive(Debug, Clone)]
+pub struct ClusterBuilder {
+    nodes: Vec<(Endpoint, u32)>,
+    cfg: ClusterCfg,
+}
+
+#[derive(Debug, Clone)]
+struct ClusterCfg {
+    failure_limit: u32,
+    retry_interval: Duration,
+    connect_timeout: Duration,
+    connection: ConnectionCfg,
+}
+
+impl ClusterBuilder {
+    /// Add a node, it gets a share of the keys in proportion to its `weight`,
+    /// which is at most 1000.
+    pub fn node(mut self, endpoint: impl Into<Endpoint>, weight: u32) -> Self {
+        self.nodes.push((endpoint.into(), weight.min(MAX_WEIGHT)));
+        self
+    }
+    /// Eject a node after this many failed requests in a row, 2 by default.
+    pub fn failure_limit(mut self, failure_limit: u32) -> Self {
+        self.cfg.failure_limit = failure_limit.max(1);
+        self
+    }
+    /// How long an ejected node is skipped before it's tried again, 30 s by default.
+    pub fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.cfg.retry_interval = retry_interval;
+        self
+    }
+    /// Give up connecting to a node after this long, 1 s by default.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.cfg.connect_timeout = connect_timeout;
+        self
+    }
+    /// Settings of the connections to the nodes.
+    pub fn connection_cfg(mut self, connection: ConnectionCfg) -> Self {
+        self.cfg.connection = connection;
+        self
+    }
+    /// Nothing is connected yet, every node is connected on its first request.
+    pub fn build(self) -> Cluster {
+        let names: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(endpoint, weight)| (endpoint.to_string(), *weight))
+            .collect();
+        let ring = Ring::new(names.iter().map(|(name, weight)| (name.as_str(), *weight)));
+        let nodes = self
+            .nodes
+            .into_iter()
+            .map(|(endpoint, _)| Node {
+                endpoint,
+                connection: Default::default(),
+                health: Default::default(),
+            })
+            .collect();
+        let inner = Inner {
+            nodes,
+            ring,
+            cfg: self.cfg,
+        };
+        Cluster {
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+/// A client of several servers, every key is stored on one of them.
+///
+/// Keys are spread over the nodes by a consistent hash ring, so adding or removing a node
+/// only moves the keys that it gains or loses. Every node is served over one [`Multiplexed`]
+/// connection. A node that fails `failure_limit` requests in a row, e.g. because it's down,
+/// is ejected: its keys go to the next nodes on the ring until it's tried again
+/// after `retry_interval`. Clone it to share it between tasks.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    nodes: Vec<Node>,
+    ring: Ring,
+    cfg: ClusterCfg,
+}
+
+#[derive(Debug)]
+struct Node {
+    endpoint: Endpoint,
+    // Held while connecting, so that concurrent requests don't connect twice.
+    connection: tokio::sync::Mutex<Connection>,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Connection {
+    client: Option<RawClient<Multiplexed>>,
+    // Counts the connections made, so that a failure only closes the one it happened on.
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    // Failed requests in a row.
+    failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Node {
+    fn health(&self) -> MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn is_up(&self, now: Instant) -> bool {
+        self.health().ejected_until.is_none_or(|until| until <= now)
+    }
+    // The client of the node with the generation of its connection.
+    async fn client(&self, cfg: &ClusterCfg) -> Result<(u64, RawClient<Multiplexed>), Error> {
+        let mut connection = self.connection.lock().await;
+        if let Some(client) = &connection.client {
+            return Ok((connection.generation, client.clone()));
+        }
+        let connect = async {
+            match &self.endpoint {
+                Endpoint::Tcp(addr) => {
+                    RawClient::connect_tcp_with_cfg(*addr, &cfg.connection).await
+                }
+                #[cfg(target_family = "unix")]
+                Endpoint::Unix(path) => {
+                    RawClient::connect_unix_with_cfg(path, &cfg.connection).await
+                }
+            }
+        };
+        let connected = tokio::time::timeout(cfg.connect_timeout, connect)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        connection.generation += 1;
+        Ok((
+            connection.generation,
+            connection.client.insert(connected).clone(),
+        ))
+    }
+    async fn run<R, Fut>(
+        &self,
+        cfg: &ClusterCfg,
+        call: impl FnOnce(RawClient<Multiplexed>) -> Fut,
+    ) -> Result<R, Error>
+    where
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let (generation, result) = match self.client(cfg).await {
+            Ok((generation, client)) => (Some(generation), call(client).await),
+            Err(err) => (None, Err(err)),
+        };
+        match result {
+            // The node is unreachable or the connection is broken, an answer is no failure.
+            Err(err @ Error::Protocol(_)) => {
+                self.fail(cfg, generation).await;
+                Err(err)
+            }
+            result => {
+                *self.health() = Health::default();
+                result
+            }
+        }
+    }
+    // `generation` is the one of the connection that broke, if there was one.
+    async fn fail(&self, cfg: &ClusterCfg, generation: Option<u64>) {
+        // The next request connects again, unless another one has already done so.
+        let mut connection = self.connection.lock().await;
+        if generation == Some(connection.generation) {
+            connection.client = None;
+        }
+        drop(connection);
+        let mut health = self.health();
+        health.failures += 1;
+        if health.failures >= cfg.failure_limit {
+            health.ejected_until = Some(Instant::now() + cfg.retry_interval);
+        }
+    }
+}
+
+// Items of a multi-key request that go to one node, with their positions in the request.
+struct Batch<T> {
+    node: usize,
+    at: Vec<usize>,
+    items: Vec<T>,
+}
+
+impl Cluster {
+    pub fn builder() -> ClusterBuilder {
+        let cfg = ClusterCfg {
+            failure_limit: 2,
+            retry_interval: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(1),
+            connection: ConnectionCfg::default(),
+        };
+        ClusterBuilder { nodes: vec![], cfg }
+    }
+    // The node of `key` among the ones that aren't ejected.
+    fn node_of(&self, key: &[u8]) -> Result<usize, Error> {
+        let (nodes, now) = (&self.inner.nodes, Instant::now());
+        self.inner
+            .ring
+            .node(key, |node| nodes[node].is_up(now))
+            .ok_or(Error::NoNodeAvailable)
+    }
+    async fn run<R, Fut>(
+        &self,
+        node: usize,
+        call: impl FnOnce(RawClient<Multiplexed>) -> Fut,
+    ) -> Result<R, Error>
+    where
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        self.inner.nodes[node].run(&self.inner.cfg, call).await
+    }
+    // Group `items`, which come with their positions, by the node of their key.
+    fn split<T>(
+        &self,
+        items: Vec<(usize, T)>,
+        key: impl Fn(&T) -> &[u8],
+    ) -> Result<Vec<Batch<T>>, Error> {
+        let mut batches: Vec<Batch<T>> = vec![];
+        for (at, item) in items {
+            let node = self.node_of(key(&item))?;
+            match batches.iter_mut().find(|batch| batch.node == node) {
+                Some(batch) => {
+                    batch.at.push(at);
+                    batch.items.push(item);
+                }
+                None => batches.push(Batch {
+                    node,
+                    at: vec![at],
+                    items: vec![item],
+                }),
+            }
+        }
+        Ok(batches)
+    }
+    // Call every node with its batch in parallel. Every batch comes back with its result.
+    async fn run_batches<T, R, F, Fut>(
+        &self,
+        batches: Vec<Batch<T>>,
+        call: F,
+    ) -> Result<Vec<(Batch<T>, Result<R, Error>)>, Error>
+    where
+        T: Clone + Send + 'static,
+        R: Send + 'static,
+        F: Fn(RawClient<Multiplexed>, Vec<T>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<R, Error>> + Send + 'static,
+    {
+        let mut tasks = JoinSet::new();
+        for batch in batches {
+            let (inner, call) = (self.inner.clone(), call.clone());
+            tasks.spawn(async move {
+                let items = batch.items.clone();
+                let node = &inner.nodes[batch.node];
+                let result = node
+                    .run(&inner.cfg, move |client| call(client, items))
+                    .await;
+                (batch, result)
+            });
+        }
+        let mut results = Vec::with_capacity(tasks.len());
+        while let Some(result) = tasks.join_next().await {
+            results.push(result.map_err(io::Error::from)?);
+        }
+        Ok(results)
+    }
+    // Call the nodes of the keys of `items` in parallel. The items of a node that fails
+    // and is ejected go to the nodes that take over its keys, so that the others' results
+    // aren't lost. Results come with the positions of the items.
+    async fn run_keyed<T, R, F, Fut>(
+        &self,
+        items: Vec<T>,
+        key: impl Fn(&T) -> &[u8],
+        call: F,
+    ) -> Result<Vec<(Vec<usize>, R)>, Error>
+    where
+        T: Clone + Send + 'static,
+        R: Send + 'static,
+        F: Fn(RawClient<Multiplexed>, Vec<T>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<R, Error>> + Send + 'static,
+    {
+        let mut results = vec![];
+        let mut items: Vec<_> = items.into_iter().enumerate().collect();
+        // Ends once every node that fails is ejected, or no node is left.
+        while !items.is_empty() {
+            let batches = self.split(items, &key)?;
+            items = vec![];
+            for (batch, result) in self.run_batches(batches, call.clone()).await? {
+                match result {
+                    Ok(result) => results.push((batch.at, result)),
+                    Err(Error::Protocol(_))
+                        if !self.inner.nodes[batch.node].is_up(Instant::now()) =>
+                    {
+                        items.extend(batch.at.into_iter().zip(batch.items));
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    // Runs `call` on the node of `key`.
+    async fn on_key<R, Fut>(
+        &self,
+        key: Vec<u8>,
+        call: impl FnOnce(RawClient<Multiplexed>, Vec<u8>) -> Fut,
+    ) -> Result<R, Error>
+    where
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let node = self.node_of(&key)?;
+        self.run(node, |client| call(client, key)).await
+    }
+
+    key_requests!();
+    /// Delete every key on every node that isn't ejected, in parallel.
+    pub async fn clear(&self) -> Result<(), Error> {
+        let now = Instant::now();
+        let batches = (0..self.inner.nodes.len())
+            .filter(|&node| self.inner.nodes[node].is_up(now))
+            .map(|node| Batch::<()> {
+                node,
+                at: vec![],
+                items: vec![],
+            })
+            .collect();
+        let cleared = self
+            .run_batches(batches, |mut client, _| async move { client.clear().await })
+            .await?;
+        for (_, result) in cleared {
+            result?;
+        }
+        Ok(())
+    }
+    /// Get several keys with one request to every node, in parallel.
+    /// Results keep the order of `keys`. The keys of a node that fails and is ejected
+    /// are asked from the nodes that take them over, like the keys of the `_many` methods below.
+    pub async fn get_many<K>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let keys: Vec<Vec<u8>> = keys.into_iter().map(Into::into).collect();
+        let mut values = vec![None; keys.len()];
+        let call =
+            |mut client: RawClient<Multiplexed>, keys| async move { client.get_many(keys).await };
+        for (at, batch) in self.run_keyed(keys, |key| key, call).await? {
+            for (at, value) in at.into_iter().zip(batch) {
+                values[at] = value;
+            }
+        }
+        Ok(values)
+    }
+    /// Set several keys with one request to every node, in parallel.
+    /// Items with `None` expiration never expire.
+    pub async fn set_many<K>(
+        &self,
+        items: impl IntoIterator<Item = (K, Vec<u8>, Option<Duration>)>,
+    ) -> Result<(), Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let items = items
+            .into_iter()
+            .map(|(key, value, ttl)| (key.into(), value, ttl))
+            .collect();
+        let call =
+            |mut client: RawClient<Multiplexed>, items| async move { client.set_many(items).await };
+        self.run_keyed(items, |(key, _, _)| key, call).await?;
+        Ok(())
+    }
+    /// Delete several keys with one request to every node, in parallel.
+    /// Each flag tells whether the corresponding key was present.
+    pub async fn delete_many<K>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<bool>, Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let keys: Vec<Vec<u8>> = keys.into_iter().map(Into::into).collect();
+        let mut deleted = vec![false; keys.len()];
+        let call = |mut client: RawClient<Multiplexed>, keys| async move {
+            client.delete_many(keys).await
+        };
+        for (at, batch) in self.run_keyed(keys, |key| key, call).await? {
+            for (at, flag) in at.into_iter().zip(batch) {
+                deleted[at] = flag;
+            }
+        }
+        Ok(deleted)
+    }
+}
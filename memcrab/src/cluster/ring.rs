@@ -0,0 +1,49 @@
+// Points of a node on the ring for every unit of its weight.
+const POINTS_PER_WEIGHT: u32 = 160;
+
+/// A ketama-style consistent hash ring. Every node gets points on the ring in proportion
+/// to its weight, a key belongs to the node of the first point at or after its hash.
+/// Adding or removing a node only moves the keys between its points and the ones before them.
+#[derive(Debug, Clone)]
+pub(crate) struct Ring {
+    // Sorted by hash, with the index of the node of each point.
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    /// `nodes` are the names and weights of the nodes. A name, not an index, places a node,
+    /// so that clients with the same nodes in another order agree.
+    pub fn new<'a>(nodes: impl IntoIterator<Item = (&'a str, u32)>) -> Self {
+        let mut points = vec![];
+        for (node, (name, weight)) in nodes.into_iter().enumerate() {
+            let count = weight.saturating_mul(POINTS_PER_WEIGHT);
+            points.extend((0..count).map(|i| (hash(format!("{name}-{i}").as_bytes()), node)));
+        }
+        points.sort_unstable();
+        Self { points }
+    }
+    /// The node of `key`, skipping the points of nodes that aren't `up`.
+    pub fn node(&self, key: &[u8], mut up: impl FnMut(usize) -> bool) -> Option<usize> {
+        let key = hash(key);
+        let start = self.points.partition_point(|&(point, _)| point < key);
+        let (below, above) = self.points.split_at(start);
+        above
+            .iter()
+            .chain(below)
+            .map(|&(_, node)| node)
+            .find(|&node| up(node))
+    }
+}
+
+// 64-bit FNV-1a with the finalizer of MurmurHash3 for avalanche. It's stable across
+// platforms and versions of Rust, unlike `DefaultHasher`, so all clients build the same ring.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
@@ -1,7 +1,78 @@
+mod multiplexed;
+mod reconnecting;
 mod tcp;
 #[cfg(target_family = "unix")]
 mod unix;
 
+pub use crate::pool::Pooled;
+pub use multiplexed::Multiplexed;
+pub use reconnecting::{Reconnecting, RetryPolicy};
 pub use tcp::Tcp;
 #[cfg(target_family = "unix")]
 pub use unix::Unix;
+
+use async_trait::async_trait;
+use memcrab_protocol::{Request, Response, Socket, DEFAULT_MAX_PAYLOAD_LEN};
+
+use crate::{Endpoint, Error, Rpc};
+
+/// Settings of a new connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionCfg {
+    max_payload_len: u64,
+}
+
+impl Default for ConnectionCfg {
+    fn default() -> Self {
+        Self {
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN,
+        }
+    }
+}
+
+impl ConnectionCfg {
+    /// Refuse responses with a payload bigger than `max_payload_len` bytes.
+    pub fn max_payload_len(mut self, max_payload_len: u64) -> Self {
+        self.max_payload_len = max_payload_len;
+        self
+    }
+    pub(crate) fn socket<S>(&self, stream: S) -> Socket<S> {
+        Socket::new(stream).with_max_payload_len(self.max_payload_len)
+    }
+}
+
+/// An exclusive connection to any kind of [`Endpoint`].
+#[derive(Debug)]
+pub(crate) enum Conn {
+    Tcp(Tcp),
+    #[cfg(target_family = "unix")]
+    Unix(Unix),
+}
+
+impl Conn {
+    pub async fn connect(endpoint: &Endpoint, cfg: &ConnectionCfg) -> Result<Self, Error> {
+        let conn = match endpoint {
+            Endpoint::Tcp(addr) => {
+                let stream = tokio::net::TcpStream::connect(addr).await?;
+                Self::Tcp(Tcp::from_stream(stream, cfg))
+            }
+            #[cfg(target_family = "unix")]
+            Endpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                Self::Unix(Unix::from_stream(stream, cfg))
+            }
+        };
+        Ok(conn)
+    }
+}
+
+#[async_trait]
+impl Rpc for Conn {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        match self {
+            Self::Tcp(conn) => conn.call(request).await,
+            #[cfg(target_family = "unix")]
+            Self::Unix(conn) => conn.call(request).await,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{ReadHalf, WriteHalf},
+    sync::{mpsc, oneshot},
+};
+
+use super::ConnectionCfg;
+use crate::{Error, Rpc};
+use memcrab_protocol::{AsyncRead, AsyncWrite, Msg, Request, RequestId, Response, Socket};
+
+const QUEUE_SIZE: usize = 1024;
+
+type Reply = oneshot::Sender<Result<Response, Error>>;
+
+/// A connection that can be cloned and shared between tasks.
+///
+/// Requests from all clones are written to one socket without waiting for
+/// previous responses. Every request is tagged with a request id, which the
+/// server echoes back, so responses are routed to the tasks that sent them.
+#[derive(Debug, Clone)]
+pub struct Multiplexed {
+    queue: mpsc::Sender<(Request, Reply)>,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    replies: HashMap<RequestId, Reply>,
+    closed: bool,
+}
+
+impl Pending {
+    fn close(&mut self) {
+        self.closed = true;
+        for (_, reply) in self.replies.drain() {
+            let _ = reply.send(Err(connection_closed()));
+        }
+    }
+}
+
+impl Multiplexed {
+    pub(crate) fn from_stream<S>(stream: S, cfg: &ConnectionCfg) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let (queue, requests) = mpsc::channel(QUEUE_SIZE);
+
+        tokio::spawn(write_requests(
+            cfg.socket(writer),
+            requests,
+            pending.clone(),
+        ));
+        tokio::spawn(read_responses(cfg.socket(reader), pending));
+        Self { queue }
+    }
+}
+
+async fn write_requests<S>(
+    mut socket: Socket<WriteHalf<S>>,
+    mut requests: mpsc::Receiver<(Request, Reply)>,
+    pending: Arc<Mutex<Pending>>,
+) where
+    S: AsyncWrite,
+{
+    let mut next_id: RequestId = 0;
+    while let Some((request, reply)) = requests.recv().await {
+        let id = {
+            let mut pending = pending.lock().unwrap();
+            if pending.closed {
+                let _ = reply.send(Err(connection_closed()));
+                continue;
+            }
+            while pending.replies.contains_key(&next_id) {
+                next_id = next_id.wrapping_add(1);
+            }
+            pending.replies.insert(next_id, reply);
+            next_id
+        };
+        next_id = next_id.wrapping_add(1);
+
+        if let Err(err) = socket.send_with_id(id, Msg::Request(request)).await {
+            let mut pending = pending.lock().unwrap();
+            if let Some(reply) = pending.replies.remove(&id) {
+                let _ = reply.send(Err(err.into()));
+            }
+            pending.close();
+        }
+    }
+    // Every handle is dropped, let the server close the connection.
+    let _ = socket.shutdown().await;
+}
+
+async fn read_responses<S>(mut socket: Socket<ReadHalf<S>>, pending: Arc<Mutex<Pending>>)
+where
+    S: AsyncRead,
+{
+    loop {
+        let (id, response) = match socket.recv_with_id().await {
+            Ok((id, Msg::Response(response))) => (id, response),
+            Ok((_, Msg::Request(_))) | Err(_) => break,
+        };
+        let reply = pending.lock().unwrap().replies.remove(&id);
+        if let Some(reply) = reply {
+            let _ = reply.send(Ok(response));
+        }
+    }
+    pending.lock().unwrap().close();
+}
+
+fn connection_closed() -> Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "connection is closed").into()
+}
+
+#[async_trait]
+impl Rpc for Multiplexed {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        let (reply, response) = oneshot::channel();
+        self.queue
+            .send((request, reply))
+            .await
+            .map_err(|_| connection_closed())?;
+        response.await.map_err(|_| connection_closed())?
+    }
+}
@@ -0,0 +1,146 @@
+use async_trait::async_trait;
+use std::time::Duration;
+
+use super::{Conn, ConnectionCfg};
+use crate::{Endpoint, Error, Rpc};
+use memcrab_protocol::{Error as ProtocolError, Request, Response};
+
+/// How a [`Reconnecting`] connection tries again after a failure.
+///
+/// Every failed attempt is followed by a backoff that starts at `initial_backoff`
+/// and doubles up to `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Attempts after the first one, 3 by default.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+    /// Backoff after the first failed attempt, 50ms by default.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+    /// Limit of the backoff, 2s by default.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+}
+
+/// A connection that survives restarts of the server.
+///
+/// A broken connection is closed and a new one is made for the next request. Making it is
+/// retried under the [`RetryPolicy`], so is a request that may safely be sent twice:
+/// `Get`, `Ping` and `Delete`. Any other request that fails after it was sent is not retried,
+/// because the server may have handled it. Once the retries run out, the request fails with
+/// [`Error::RetriesExhausted`]. A response that cannot be parsed closes the connection too,
+/// but fails the request at once, since it would be the same on a new connection.
+#[derive(Debug)]
+pub struct Reconnecting {
+    endpoint: Endpoint,
+    cfg: ConnectionCfg,
+    policy: RetryPolicy,
+    conn: Option<Conn>,
+}
+
+impl Reconnecting {
+    pub(crate) async fn connect(
+        endpoint: Endpoint,
+        cfg: ConnectionCfg,
+        policy: RetryPolicy,
+    ) -> Result<Self, Error> {
+        let conn = Conn::connect(&endpoint, &cfg).await?;
+        Ok(Self {
+            endpoint,
+            cfg,
+            policy,
+            conn: Some(conn),
+        })
+    }
+}
+
+#[async_trait]
+impl Rpc for Reconnecting {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        let idempotent = matches!(
+            request,
+            Request::Get(_) | Request::Ping | Request::Delete(_)
+        );
+        let mut retry = Retry::new(&self.policy);
+        loop {
+            // Taken out for the call, so that a cancelled call drops a connection
+            // that may still get its response.
+            let mut conn = match self.conn.take() {
+                Some(conn) => conn,
+                None => match Conn::connect(&self.endpoint, &self.cfg).await {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        retry.after(err).await?;
+                        continue;
+                    }
+                },
+            };
+            if !idempotent {
+                let result = conn.call(request).await;
+                if !matches!(result, Err(Error::Protocol(_))) {
+                    self.conn = Some(conn);
+                }
+                return result;
+            }
+            match conn.call(request.clone()).await {
+                Err(err @ Error::Protocol(ProtocolError::IO(_))) => retry.after(err).await?,
+                Err(err @ Error::Protocol(_)) => return Err(err),
+                result => {
+                    self.conn = Some(conn);
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+struct Retry<'a> {
+    policy: &'a RetryPolicy,
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl<'a> Retry<'a> {
+    fn new(policy: &'a RetryPolicy) -> Self {
+        Self {
+            policy,
+            attempts: 0,
+            backoff: policy.initial_backoff,
+        }
+    }
+    // Waits for the next attempt after a failed one, or gives up with its error.
+    async fn after(&mut self, err: Error) -> Result<(), Error> {
+        self.attempts += 1;
+        if self.attempts > self.policy.max_retries {
+            return Err(Error::RetriesExhausted {
+                attempts: self.attempts,
+                last: Box::new(err),
+            });
+        }
+        tokio::time::sleep(self.backoff).await;
+        self.backoff = (self.backoff * 2).min(self.policy.max_backoff);
+        Ok(())
+    }
+}
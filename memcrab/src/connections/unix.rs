@@ -1,16 +1,18 @@
 use async_trait::async_trait;
 use tokio::net::UnixStream;
 
+use super::ConnectionCfg;
 use crate::{Error, Rpc};
-use memcrab_protocol::{Msg, ParseError, Request, Response, Socket};
+use memcrab_protocol::{Error as ProtocolError, Msg, Request, Response, Socket};
 
+#[derive(Debug)]
 pub struct Unix {
     inner: Socket<UnixStream>,
 }
 
 impl Unix {
-    pub(crate) fn from_stream(stream: UnixStream) -> Self {
-        let inner = Socket::new(stream);
+    pub(crate) fn from_stream(stream: UnixStream, cfg: &ConnectionCfg) -> Self {
+        let inner = cfg.socket(stream);
         Self { inner }
     }
 }
@@ -21,7 +23,7 @@ impl Rpc for Unix {
         self.inner.send(Msg::Request(request)).await?;
         match self.inner.recv().await? {
             Msg::Response(resp) => Ok(resp),
-            _ => Err(Error::Parse(ParseError::UnknownMsgKind)),
+            msg => Err(ProtocolError::InvalidMsg(msg).into()),
         }
     }
 }
@@ -0,0 +1,44 @@
+use memcrab_protocol::{Error as ProtocolError, Response};
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("protocol error")]
+    Protocol(#[from] ProtocolError),
+
+    /// The server failed to handle the request.
+    #[error("server error: {0}")]
+    Server(String),
+
+    /// The stored value is not a counter.
+    #[error("value is not a counter")]
+    NotCounter,
+
+    /// No connection of a [`Pool`](crate::Pool) became free within its checkout timeout.
+    #[error("no pooled connection within the checkout timeout")]
+    CheckoutTimeout,
+
+    /// Every node of a [`Cluster`](crate::Cluster) is ejected.
+    #[error("no node of the cluster is available")]
+    NoNodeAvailable,
+
+    /// A request to a [`Reconnecting`](crate::connections::Reconnecting) connection failed
+    /// on every attempt that its retry policy allows.
+    #[error("request failed after {attempts} attempts: {last}")]
+    RetriesExhausted {
+        attempts: u32,
+        #[source]
+        last: Box<Error>,
+    },
+
+    /// The server answered with a response that doesn't belong to the request.
+    #[error("unexpected response: {0:?}")]
+    UnexpectedResponse(Response),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Protocol(err.into())
+    }
+}
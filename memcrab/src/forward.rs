@@ -0,0 +1,54 @@
+/// Defines the requests on one key for a client that is built on [`RawClient`](crate::RawClient),
+/// so that a new request is added here once for all of them.
+///
+/// Every method calls `self.on_key(key, call)`, which has to run `call` with a client
+/// for the key and the key.
+macro_rules! key_requests {
+    () => {
+        $crate::forward::key_requests! {
+            @methods
+            get() -> Option<Vec<u8>>;
+            set(value: Vec<u8>) -> ();
+            set_with_expiration(value: Vec<u8>, ttl: std::time::Duration) -> ();
+            set_with_deadline(value: Vec<u8>, deadline: std::time::SystemTime) -> ();
+            delete() -> bool;
+            ttl() -> Option<Option<std::time::Duration>>;
+            touch(ttl: std::time::Duration) -> bool;
+            persist() -> bool;
+            get_and_touch(ttl: std::time::Duration) -> Option<Vec<u8>>;
+            add(value: Vec<u8>, ttl: Option<std::time::Duration>) -> bool;
+            replace(value: Vec<u8>, ttl: Option<std::time::Duration>) -> bool;
+            append(value: Vec<u8>) -> bool;
+            prepend(value: Vec<u8>) -> bool;
+            get_with_version() -> Option<(Vec<u8>, $crate::Version)>;
+            compare_and_swap(
+                value: Vec<u8>,
+                ttl: Option<std::time::Duration>,
+                version: $crate::Version
+            ) -> $crate::CasOutcome;
+            increment(delta: u64) -> Option<u64>;
+            increment_or(delta: u64, initial: u64, ttl: Option<std::time::Duration>) -> u64;
+            decrement(delta: u64) -> Option<u64>;
+            decrement_or(delta: u64, initial: u64, ttl: Option<std::time::Duration>) -> u64;
+        }
+    };
+    (@methods $( $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty; )*) => {
+        $(
+            #[doc = concat!(
+                "See [`RawClient::", stringify!($name), "`](crate::RawClient::", stringify!($name), ")."
+            )]
+            pub async fn $name(
+                &self,
+                key: impl Into<Vec<u8>>,
+                $($arg: $ty),*
+            ) -> Result<$ret, $crate::Error> {
+                self.on_key(key.into(), move |mut client, key| async move {
+                    client.$name(key, $($arg),*).await
+                })
+                .await
+            }
+        )*
+    };
+}
+
+pub(crate) use key_requests;
@@ -26,15 +26,177 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 ```
+
+#### Shared connection
+```no_run
+use memcrab::{RawClient, connections::Multiplexed, Error};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let addr = "127.0.0.1:80".parse().unwrap();
+    let client = RawClient::<Multiplexed>::connect_tcp(addr).await?;
+
+    let mut handle = client.clone();
+    tokio::spawn(async move { handle.set("date", vec![2, 3, 24]).await });
+    let name = client.clone().get("name").await?;
+    println!("{:?}", name);
+    Ok(())
+}
+```
+
+#### Connection pool
+
+Exclusive connections shared by many tasks, with the client API on `&self`.
+A request waits for a free connection at most `checkout_timeout`, idle connections are checked
+with a `Ping` every `health_check_interval`, and a connection that fails a request is closed.
+```no_run
+use memcrab::{Error, Pool};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let addr: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let pool = Pool::builder(addr)
+        .min_size(2)
+        .max_size(16)
+        .checkout_timeout(Duration::from_secs(1))
+        .build()
+        .await?;
+
+    let handle = pool.clone();
+    tokio::spawn(async move { handle.set("date", vec![2, 3, 24]).await });
+    let name = pool.get("name").await?;
+    println!("{:?}", name);
+    Ok(())
+}
+```
+
+#### Reconnecting connection
+
+A connection that is made again when it breaks, e.g. because the server restarted.
+`Get`, `Ping` and `Delete` are retried with an exponential backoff, other requests are not
+sent twice, so they fail once and the next request reconnects.
+Once the retries run out, the request fails with `Error::RetriesExhausted`.
+A response that cannot be parsed is not retried, it would be the same again.
+```no_run
+use memcrab::{
+    connections::{ConnectionCfg, Reconnecting, RetryPolicy},
+    Error, RawClient,
+};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let addr: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let policy = RetryPolicy::default()
+        .max_retries(5)
+        .max_backoff(Duration::from_secs(1));
+    let mut client =
+        RawClient::<Reconnecting>::connect_with_cfg(addr, &ConnectionCfg::default(), policy)
+            .await?;
+
+    client.set("date", vec![2, 3, 24]).await?;
+    let name = client.get("name").await?;
+    println!("{:?}", name);
+    Ok(())
+}
+```
+
+### Cluster
+
+Spreads keys over several servers with a consistent hash ring, a node with weight 2
+gets twice as many keys. Multi-key requests go to every node in parallel.
+A node that keeps failing is skipped for a while, its keys go to the other nodes meanwhile.
+```no_run
+use memcrab::{Cluster, Error};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let cluster = Cluster::builder()
+        .node("10.0.0.1:9090".parse::<std::net::SocketAddr>().unwrap(), 1)
+        .node("10.0.0.2:9090".parse::<std::net::SocketAddr>().unwrap(), 2)
+        .build();
+
+    cluster.set("date", vec![2, 3, 24]).await?;
+    let values = cluster.get_many(["date", "name"]).await?;
+    println!("{:?}", values);
+    Ok(())
+}
+```
 */
 
+mod cluster;
+mod err;
+mod forward;
+mod pool;
 #[allow(unused_variables)]
 mod raw_client;
 
 pub mod connections;
 
-pub use memcrab_protocol::Error;
-pub use raw_client::{RawClient, Rpc};
+pub use cluster::{Cluster, ClusterBuilder, Endpoint};
+pub use err::Error;
+pub use memcrab_protocol::{SegmentStats, Stats, Version};
+pub use pool::{Pool, PoolBuilder};
+pub use raw_client::{CasOutcome, RawClient, Rpc};
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use crate::cluster::Ring;
+
+    fn ring(weights: &[u32]) -> Ring {
+        let names: Vec<_> = (0..weights.len())
+            .map(|i| format!("10.0.0.{i}:9090"))
+            .collect();
+        Ring::new(
+            names
+                .iter()
+                .map(String::as_str)
+                .zip(weights.iter().copied()),
+        )
+    }
+
+    fn owners(ring: &Ring) -> Vec<usize> {
+        (0..10_000)
+            .map(|i| ring.node(format!("key-{i}").as_bytes(), |_| true).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_ring_weights() {
+        let owners = owners(&ring(&[1, 1, 1, 1]));
+        for node in 0..4 {
+            let share = owners.iter().filter(|&&owner| owner == node).count();
+            assert!((1_500..3_500).contains(&share), "{share}");
+        }
+
+        let owners = self::owners(&ring(&[1, 3]));
+        let heavy = owners.iter().filter(|&&owner| owner == 1).count();
+        assert!((6_500..8_500).contains(&heavy), "{heavy}");
+    }
+
+    #[test]
+    fn test_ring_moves_few_keys() {
+        let before = owners(&ring(&[1, 1, 1, 1]));
+        let after = owners(&ring(&[1, 1, 1, 1, 1]));
+        let moved: Vec<_> = (0..before.len())
+            .filter(|&i| before[i] != after[i])
+            .collect();
+        // only to the new node, about a fifth of the keys
+        assert!(moved.iter().all(|&i| after[i] == 4));
+        assert!(moved.len() < 3_000, "{}", moved.len());
+
+        // an ejected node's keys go to the others, the rest stay
+        let ring = ring(&[1, 1, 1, 1]);
+        for i in 0..1_000 {
+            let key = format!("key-{i}");
+            let owner = ring.node(key.as_bytes(), |_| true).unwrap();
+            let fallback = ring.node(key.as_bytes(), |node| node != 0).unwrap();
+            assert_ne!(fallback, 0);
+            if owner != 0 {
+                assert_eq!(fallback, owner);
+            }
+        }
+        assert_eq!(ring.node(b"key", |_| false), None);
+    }
+}
@@ -0,0 +1,292 @@
+use async_trait::async_trait;
+use memcrab_protocol::{Request, Response};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::{
+    connections::{Conn, ConnectionCfg},
+    forward::key_requests,
+    Endpoint, Error, RawClient, Rpc, Stats,
+};
+
+/// Settings of a [`Pool`].
+#[derive(Debug, Clone)]
+pub struct PoolBuilder {
+    endpoint: Endpoint,
+    cfg: PoolCfg,
+}
+
+#[derive(Debug, Clone)]
+struct PoolCfg {
+    min_size: usize,
+    max_size: usize,
+    checkout_timeout: Duration,
+    health_check_interval: Duration,
+    connection: ConnectionCfg,
+}
+
+impl PoolBuilder {
+    /// Keep at least this many connections open, 0 by default.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.cfg.min_size = min_size;
+        self
+    }
+    /// Open at most this many connections, 10 by default. 0 counts as 1.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.cfg.max_size = max_size.max(1);
+        self
+    }
+    /// How long a request waits for a connection, including the time to open one.
+    /// 5 s by default.
+    pub fn checkout_timeout(mut self, checkout_timeout: Duration) -> Self {
+        self.cfg.checkout_timeout = checkout_timeout;
+        self
+    }
+    /// How often idle connections are checked with a `Ping`, 30 s by default.
+    /// Broken ones are closed and replaced, if there are less than `min_size` left.
+    pub fn health_check_interval(mut self, health_check_interval: Duration) -> Self {
+        self.cfg.health_check_interval = health_check_interval;
+        self
+    }
+    /// Settings of the connections of the pool.
+    pub fn connection_cfg(mut self, connection: ConnectionCfg) -> Self {
+        self.cfg.connection = connection;
+        self
+    }
+    /// Open `min_size` connections and start the health checks.
+    pub async fn build(self) -> Result<Pool, Error> {
+        let min_size = self.cfg.min_size.min(self.cfg.max_size);
+        let inner = Arc::new(Inner {
+            endpoint: self.endpoint,
+            permits: Arc::new(Semaphore::new(self.cfg.max_size)),
+            idle: Mutex::default(),
+            cfg: self.cfg,
+        });
+        for _ in 0..min_size {
+            let conn = inner.connect().await?;
+            inner.idle().push_back(conn);
+        }
+        tokio::spawn(check_health(Arc::downgrade(&inner)));
+        Ok(Pool { inner })
+    }
+}
+
+/// A pool of connections to one server, with the client API on `&self`.
+///
+/// Every request checks out a connection, waiting for one at most `checkout_timeout`,
+/// and returns it when it's answered. A connection that fails a request is closed instead.
+/// [`Pool::checkout`] keeps a connection for several requests. Clone it to share it between tasks,
+/// the health checks stop when the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    endpoint: Endpoint,
+    cfg: PoolCfg,
+    // One for every connection that is open and not idle, or that is being opened.
+    permits: Arc<Semaphore>,
+    idle: Mutex<VecDeque<Conn>>,
+}
+
+impl Inner {
+    fn idle(&self) -> MutexGuard<'_, VecDeque<Conn>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    async fn connect(&self) -> Result<Conn, Error> {
+        Conn::connect(&self.endpoint, &self.cfg.connection).await
+    }
+    // Connections that are open or being opened.
+    fn size(&self) -> usize {
+        let busy = self.cfg.max_size - self.permits.available_permits();
+        busy + self.idle().len()
+    }
+}
+
+// Pings every idle connection once an interval, until the pool is dropped.
+async fn check_health(pool: Weak<Inner>) {
+    let Some(interval) = pool.upgrade().map(|pool| pool.cfg.health_check_interval) else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        let idle = pool.idle().len();
+        for _ in 0..idle {
+            // Checked out like for a request, so that the pool doesn't grow meanwhile.
+            let Ok(permit) = pool.permits.clone().try_acquire_owned() else {
+                break;
+            };
+            // The least recently used one, it goes to the front once checked.
+            let Some(mut conn) = pool.idle().pop_back() else {
+                break;
+            };
+            let ping = tokio::time::timeout(pool.cfg.checkout_timeout, conn.call(Request::Ping));
+            if matches!(ping.await, Ok(Ok(Response::Pong))) {
+                pool.idle().push_front(conn);
+            }
+            drop(permit);
+        }
+        while pool.size() < pool.cfg.min_size.min(pool.cfg.max_size) {
+            let Ok(permit) = pool.permits.clone().try_acquire_owned() else {
+                break;
+            };
+            // Tried again in the next round.
+            let Ok(conn) = pool.connect().await else {
+                break;
+            };
+            pool.idle().push_back(conn);
+            drop(permit);
+        }
+    }
+}
+
+/// A connection checked out of a [`Pool`]. It goes back to the pool when it's dropped,
+/// unless a request on it has failed or has been cancelled.
+#[derive(Debug)]
+pub struct Pooled {
+    conn: Option<Conn>,
+    // Set while a request is in flight, a cancelled one leaves its response on the connection.
+    broken: bool,
+    pool: Arc<Inner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+#[async_trait]
+impl Rpc for Pooled {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        let conn = self.conn.as_mut().expect("connection is checked out");
+        self.broken = true;
+        let result = conn.call(request).await;
+        self.broken = matches!(result, Err(Error::Protocol(_)));
+        result
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        if let (Some(conn), false) = (self.conn.take(), self.broken) {
+            // Checked out first again, so that few connections are enough when it's quiet.
+            self.pool.idle().push_front(conn);
+        }
+    }
+}
+
+impl Pool {
+    pub fn builder(endpoint: impl Into<Endpoint>) -> PoolBuilder {
+        let cfg = PoolCfg {
+            min_size: 0,
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(5),
+            health_check_interval: Duration::from_secs(30),
+            connection: ConnectionCfg::default(),
+        };
+        PoolBuilder {
+            endpoint: endpoint.into(),
+            cfg,
+        }
+    }
+    /// Take a connection for several requests, it goes back to the pool when the client is dropped.
+    /// Waits at most `checkout_timeout` for an idle connection or for room to open one.
+    pub async fn checkout(&self) -> Result<RawClient<Pooled>, Error> {
+        let deadline = Instant::now() + self.inner.cfg.checkout_timeout;
+        let permits = self.inner.permits.clone();
+        let permit = tokio::time::timeout_at(deadline, permits.acquire_owned())
+            .await
+            .map_err(|_| Error::CheckoutTimeout)?
+            .expect("pool's semaphore is never closed");
+        let idle = self.inner.idle().pop_front();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => tokio::time::timeout_at(deadline, self.inner.connect())
+                .await
+                .map_err(|_| Error::CheckoutTimeout)??,
+        };
+        Ok(RawClient::new(Pooled {
+            conn: Some(conn),
+            broken: false,
+            pool: self.inner.clone(),
+            _permit: permit,
+        }))
+    }
+    /// Connections that are open, idle or checked out.
+    pub fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    // Runs `call` on a connection that is checked out for it.
+    async fn on_key<R, Fut>(
+        &self,
+        key: Vec<u8>,
+        call: impl FnOnce(RawClient<Pooled>, Vec<u8>) -> Fut,
+    ) -> Result<R, Error>
+    where
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        call(self.checkout().await?, key).await
+    }
+
+    key_requests!();
+    /// See [`RawClient::clear`].
+    pub async fn clear(&self) -> Result<(), Error> {
+        self.checkout().await?.clear().await
+    }
+    /// See [`RawClient::ping`].
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.checkout().await?.ping().await
+    }
+    /// See [`RawClient::stats`].
+    pub async fn stats(&self) -> Result<Stats, Error> {
+        self.checkout().await?.stats().await
+    }
+    /// See [`RawClient::save`].
+    pub async fn save(&self) -> Result<(), Error> {
+        self.checkout().await?.save().await
+    }
+    /// See [`RawClient::promote`].
+    pub async fn promote(&self) -> Result<(), Error> {
+        self.checkout().await?.promote().await
+    }
+    /// See [`RawClient::get_many`].
+    pub async fn get_many<K>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        self.checkout().await?.get_many(keys).await
+    }
+    /// See [`RawClient::set_many`].
+    pub async fn set_many<K>(
+        &self,
+        items: impl IntoIterator<Item = (K, Vec<u8>, Option<Duration>)>,
+    ) -> Result<(), Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        self.checkout().await?.set_many(items).await
+    }
+    /// See [`RawClient::delete_many`].
+    pub async fn delete_many<K>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<bool>, Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        self.checkout().await?.delete_many(keys).await
+    }
+}
@@ -1,6 +1,10 @@
-use crate::{connections::*, Error};
-use memcrab_protocol::{Msg, Request, Response};
-use std::{net::SocketAddr, path::Path};
+use crate::{connections::*, Endpoint, Error};
+use memcrab_protocol::{Expiration, Item, Request, Response, Stats, Version};
+use std::{
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
 
 #[async_trait::async_trait]
 pub trait Rpc
@@ -10,25 +14,39 @@ where
     async fn call(&mut self, request: Request) -> Result<Response, Error>;
 }
 
+/// Outcome of [`RawClient::compare_and_swap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CasOutcome {
+    Stored,
+    /// The value was changed since its version was read.
+    VersionMismatch,
+    NotFound,
+}
+
+#[derive(Debug, Clone)]
 pub struct RawClient<C> {
     conn: C,
 }
 
 impl<C> RawClient<C> {
-    fn new(conn: C) -> Self {
+    pub(crate) fn new(conn: C) -> Self {
         Self { conn }
     }
 }
 
+// Every request can be answered with an error by the server.
 fn invalid_resp(resp: Response) -> Error {
-    Error::InvalidMsg(Msg::Response(resp))
+    match resp {
+        Response::Error(msg) => Error::Server(msg),
+        resp => Error::UnexpectedResponse(resp),
+    }
 }
 
 impl<C> RawClient<C>
 where
     C: Rpc,
 {
-    pub async fn get(&mut self, key: impl Into<String>) -> Result<Option<Vec<u8>>, Error> {
+    pub async fn get(&mut self, key: impl Into<Vec<u8>>) -> Result<Option<Vec<u8>>, Error> {
         let key = key.into();
         match self.conn.call(Request::Get(key)).await? {
             Response::Value(val) => Ok(Some(val)),
@@ -36,35 +54,418 @@ where
             resp => Err(invalid_resp(resp)),
         }
     }
-    pub async fn set(&mut self, key: impl Into<String>, value: Vec<u8>) -> Result<(), Error> {
-        let key = key.into();
+    /// Set a value that never expires.
+    pub async fn set(&mut self, key: impl Into<Vec<u8>>, value: Vec<u8>) -> Result<(), Error> {
+        self.call_set(key.into(), value, Expiration::Never).await
+    }
+    /// Set a value that expires `ttl` from now, with millisecond precision.
+    pub async fn set_with_expiration(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.call_set(key.into(), value, Expiration::After(ttl))
+            .await
+    }
+    /// Set a value that expires at `deadline`, a Unix time with millisecond precision.
+    /// A deadline in the past makes the value expire at once.
+    pub async fn set_with_deadline(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        deadline: SystemTime,
+    ) -> Result<(), Error> {
+        self.call_set(key.into(), value, Expiration::At(deadline))
+            .await
+    }
+    async fn call_set(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expiration: Expiration,
+    ) -> Result<(), Error> {
         let request = Request::Set {
             key,
             value,
-            expiration: 0,
+            expiration,
         };
+        self.call_ok(request).await
+    }
+    /// Delete a key. Returns `false` if the key is missing.
+    pub async fn delete(&mut self, key: impl Into<Vec<u8>>) -> Result<bool, Error> {
+        self.call_found(Request::Delete(key.into())).await
+    }
+    /// Delete every key.
+    pub async fn clear(&mut self) -> Result<(), Error> {
+        self.call_ok(Request::Clear).await
+    }
+    /// Check that the server is alive.
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        match self.conn.call(Request::Ping).await? {
+            Response::Pong => Ok(()),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Metrics of the server: counters and memory of every segment, connections and uptime.
+    pub async fn stats(&mut self) -> Result<Stats, Error> {
+        match self.conn.call(Request::Stats).await? {
+            Response::Stats(stats) => Ok(stats),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Make the server write a snapshot of its cache to the file it is configured with.
+    /// Returns once the snapshot is written.
+    pub async fn save(&mut self) -> Result<(), Error> {
+        self.call_ok(Request::Save).await
+    }
+    /// Make a replica stop replicating its primary and accept writes.
+    /// A server that isn't a replica has nothing to do.
+    /// The server refuses it unless it allows replication.
+    pub async fn promote(&mut self) -> Result<(), Error> {
+        self.call_ok(Request::Promote).await
+    }
+    async fn call_ok(&mut self, request: Request) -> Result<(), Error> {
         match self.conn.call(request).await? {
             Response::Ok => Ok(()),
             resp => Err(invalid_resp(resp)),
         }
     }
+    /// Remaining time to live of a key.
+    /// Returns `None` if the key is missing and `Some(None)` if it never expires.
+    pub async fn ttl(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+    ) -> Result<Option<Option<Duration>>, Error> {
+        match self.conn.call(Request::Ttl(key.into())).await? {
+            Response::Ttl(ttl) => Ok(Some(ttl)),
+            Response::KeyNotFound => Ok(None),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Restart the expiration of a key with `ttl`. Returns `false` if the key is missing.
+    pub async fn touch(&mut self, key: impl Into<Vec<u8>>, ttl: Duration) -> Result<bool, Error> {
+        let request = Request::Touch {
+            key: key.into(),
+            expiration: Expiration::After(ttl),
+        };
+        self.call_found(request).await
+    }
+    /// Remove the expiration of a key. Returns `false` if the key is missing.
+    pub async fn persist(&mut self, key: impl Into<Vec<u8>>) -> Result<bool, Error> {
+        self.call_found(Request::Persist(key.into())).await
+    }
+    /// Get a value and restart its expiration with `ttl`, e.g. for sliding sessions.
+    pub async fn get_and_touch(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        ttl: Duration,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let request = Request::GetAndTouch {
+            key: key.into(),
+            expiration: Expiration::After(ttl),
+        };
+        match self.conn.call(request).await? {
+            Response::Value(val) => Ok(Some(val)),
+            Response::KeyNotFound => Ok(None),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    async fn call_found(&mut self, request: Request) -> Result<bool, Error> {
+        match self.conn.call(request).await? {
+            Response::Ok => Ok(true),
+            Response::KeyNotFound => Ok(false),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Set the value only if the key is missing. Returns whether it was stored.
+    pub async fn add(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, Error> {
+        let request = Request::Add {
+            key: key.into(),
+            value,
+            expiration: expiration(ttl),
+        };
+        self.call_stored(request).await
+    }
+    /// Set the value only if the key is present. Returns whether it was stored.
+    pub async fn replace(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, Error> {
+        let request = Request::Replace {
+            key: key.into(),
+            value,
+            expiration: expiration(ttl),
+        };
+        self.call_stored(request).await
+    }
+    /// Add bytes to the end of an existing value. Returns `false` if the key is missing.
+    pub async fn append(&mut self, key: impl Into<Vec<u8>>, value: Vec<u8>) -> Result<bool, Error> {
+        let request = Request::Append {
+            key: key.into(),
+            value,
+        };
+        self.call_stored(request).await
+    }
+    /// Add bytes to the beginning of an existing value. Returns `false` if the key is missing.
+    pub async fn prepend(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+    ) -> Result<bool, Error> {
+        let request = Request::Prepend {
+            key: key.into(),
+            value,
+        };
+        self.call_stored(request).await
+    }
+    async fn call_stored(&mut self, request: Request) -> Result<bool, Error> {
+        match self.conn.call(request).await? {
+            Response::Ok => Ok(true),
+            Response::NotStored => Ok(false),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Get a value together with its version, to be used in [`Self::compare_and_swap`].
+    pub async fn get_with_version(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+    ) -> Result<Option<(Vec<u8>, Version)>, Error> {
+        let key = key.into();
+        match self.conn.call(Request::GetVersioned(key)).await? {
+            Response::VersionedValue { value, version } => Ok(Some((value, version))),
+            Response::KeyNotFound => Ok(None),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Set the value only if nobody has written the key since `version` was read.
+    /// The new value expires after `ttl`, it never expires with `None`.
+    pub async fn compare_and_swap(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+        version: Version,
+    ) -> Result<CasOutcome, Error> {
+        let request = Request::Cas {
+            key: key.into(),
+            value,
+            expiration: expiration(ttl),
+            version,
+        };
+        match self.conn.call(request).await? {
+            Response::Ok => Ok(CasOutcome::Stored),
+            Response::VersionMismatch => Ok(CasOutcome::VersionMismatch),
+            Response::KeyNotFound => Ok(CasOutcome::NotFound),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Atomically add `delta` to a counter, wrapping around on overflow.
+    /// Returns the new value, or `None` if the key is missing.
+    pub async fn increment(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        delta: u64,
+    ) -> Result<Option<u64>, Error> {
+        let request = Request::Increment {
+            key: key.into(),
+            delta,
+            initial: None,
+            expiration: Expiration::Never,
+        };
+        self.call_counter(request).await
+    }
+    /// Like [`Self::increment`], but a missing counter is created with `initial` and `ttl`.
+    pub async fn increment_or(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        delta: u64,
+        initial: u64,
+        ttl: Option<Duration>,
+    ) -> Result<u64, Error> {
+        let request = Request::Increment {
+            key: key.into(),
+            delta,
+            initial: Some(initial),
+            expiration: expiration(ttl),
+        };
+        // The server creates a missing counter, so it should never report it as not found.
+        self.call_counter(request)
+            .await?
+            .ok_or_else(|| invalid_resp(Response::KeyNotFound))
+    }
+    /// Atomically subtract `delta` from a counter, stopping at zero.
+    /// Returns the new value, or `None` if the key is missing.
+    pub async fn decrement(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        delta: u64,
+    ) -> Result<Option<u64>, Error> {
+        let request = Request::Decrement {
+            key: key.into(),
+            delta,
+            initial: None,
+            expiration: Expiration::Never,
+        };
+        self.call_counter(request).await
+    }
+    /// Like [`Self::decrement`], but a missing counter is created with `initial` and `ttl`.
+    pub async fn decrement_or(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        delta: u64,
+        initial: u64,
+        ttl: Option<Duration>,
+    ) -> Result<u64, Error> {
+        let request = Request::Decrement {
+            key: key.into(),
+            delta,
+            initial: Some(initial),
+            expiration: expiration(ttl),
+        };
+        // The server creates a missing counter, so it should never report it as not found.
+        self.call_counter(request)
+            .await?
+            .ok_or_else(|| invalid_resp(Response::KeyNotFound))
+    }
+    async fn call_counter(&mut self, request: Request) -> Result<Option<u64>, Error> {
+        match self.conn.call(request).await? {
+            Response::Counter(n) => Ok(Some(n)),
+            Response::KeyNotFound => Ok(None),
+            Response::NotCounter => Err(Error::NotCounter),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Get several keys in one round trip. Results keep the order of `keys`.
+    pub async fn get_many<K>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect();
+        match self.conn.call(Request::GetMany(keys)).await? {
+            Response::Values(values) => Ok(values),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+    /// Set several keys in one round trip. Items with `None` expiration never expire.
+    /// If any item is too big for the server, none of them is stored.
+    pub async fn set_many<K>(
+        &mut self,
+        items: impl IntoIterator<Item = (K, Vec<u8>, Option<Duration>)>,
+    ) -> Result<(), Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let items = items
+            .into_iter()
+            .map(|(key, value, ttl)| Item {
+                key: key.into(),
+                value,
+                expiration: expiration(ttl),
+            })
+            .collect();
+        self.call_ok(Request::SetMany(items)).await
+    }
+    /// Delete several keys in one round trip.
+    /// Each flag tells whether the corresponding key was present.
+    pub async fn delete_many<K>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<bool>, Error>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect();
+        match self.conn.call(Request::DeleteMany(keys)).await? {
+            Response::Deleted(flags) => Ok(flags),
+            resp => Err(invalid_resp(resp)),
+        }
+    }
+}
+
+fn expiration(ttl: Option<Duration>) -> Expiration {
+    ttl.map_or(Expiration::Never, Expiration::After)
 }
 
 impl RawClient<Tcp> {
     pub async fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        Self::connect_with_cfg(addr, &ConnectionCfg::default()).await
+    }
+    pub async fn connect_with_cfg(addr: SocketAddr, cfg: &ConnectionCfg) -> Result<Self, Error> {
         use tokio::net::TcpStream;
 
         let stream = TcpStream::connect(addr).await?;
-        Ok(Self::new(Tcp::from_stream(stream)))
+        Ok(Self::new(Tcp::from_stream(stream, cfg)))
+    }
+}
+
+/// A client over a [`Multiplexed`] connection. Clone it to share the connection between tasks.
+impl RawClient<Multiplexed> {
+    pub async fn connect_tcp(addr: SocketAddr) -> Result<Self, Error> {
+        Self::connect_tcp_with_cfg(addr, &ConnectionCfg::default()).await
+    }
+    pub async fn connect_tcp_with_cfg(
+        addr: SocketAddr,
+        cfg: &ConnectionCfg,
+    ) -> Result<Self, Error> {
+        use tokio::net::TcpStream;
+
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self::new(Multiplexed::from_stream(stream, cfg)))
+    }
+    #[cfg(target_family = "unix")]
+    pub async fn connect_unix(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::connect_unix_with_cfg(path, &ConnectionCfg::default()).await
+    }
+    #[cfg(target_family = "unix")]
+    pub async fn connect_unix_with_cfg(
+        path: impl AsRef<Path>,
+        cfg: &ConnectionCfg,
+    ) -> Result<Self, Error> {
+        use tokio::net::UnixStream;
+
+        let stream = UnixStream::connect(path).await?;
+        Ok(Self::new(Multiplexed::from_stream(stream, cfg)))
     }
 }
 
 #[cfg(target_family = "unix")]
 impl RawClient<Unix> {
     pub async fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::connect_with_cfg(path, &ConnectionCfg::default()).await
+    }
+    pub async fn connect_with_cfg(
+        path: impl AsRef<Path>,
+        cfg: &ConnectionCfg,
+    ) -> Result<Self, Error> {
         use tokio::net::UnixStream;
 
         let stream = UnixStream::connect(path).await?;
-        Ok(Self::new(Unix::from_stream(stream)))
+        Ok(Self::new(Unix::from_stream(stream, cfg)))
+    }
+}
+
+/// A client over a [`Reconnecting`] connection, that keeps working when the server restarts.
+impl RawClient<Reconnecting> {
+    pub async fn connect(endpoint: impl Into<Endpoint>) -> Result<Self, Error> {
+        Self::connect_with_cfg(endpoint, &ConnectionCfg::default(), RetryPolicy::default()).await
+    }
+    pub async fn connect_with_cfg(
+        endpoint: impl Into<Endpoint>,
+        cfg: &ConnectionCfg,
+        policy: RetryPolicy,
+    ) -> Result<Self, Error> {
+        let conn = Reconnecting::connect(endpoint.into(), cfg.clone(), policy).await?;
+        Ok(Self::new(conn))
     }
 }
@@ -0,0 +1,51 @@
+use super::connect;
+use memcrab::{connections::Tcp, Error, RawClient};
+use memcrab_protocol::{Msg, Response, Socket};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn test_basic() -> anyhow::Result<()> {
+    let mut client = connect().await;
+    client.ping().await?;
+
+    client.set("a", vec![1]).await?;
+    assert!(client.delete("a").await?);
+    assert!(!client.delete("a").await?);
+    assert_eq!(client.get("a").await?, None);
+
+    let ttl = Duration::from_millis(100);
+    client.set_with_expiration("a", vec![1], ttl).await?;
+    client.set("b", vec![2]).await?;
+    assert!(client.ttl("a").await?.flatten().unwrap() <= ttl);
+
+    client.clear().await?;
+    assert_eq!(client.get_many(["a", "b"]).await?, [None, None]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unexpected_response() -> anyhow::Result<()> {
+    // A fake server that answers every request with an error, then with `Pong`.
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = Socket::new(stream);
+        let responses = [Response::Error("out of memory".to_owned()), Response::Pong];
+        for response in responses {
+            let (id, _) = socket.recv_with_id().await.unwrap();
+            socket
+                .send_with_id(id, Msg::Response(response))
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    let err = client.set("a", vec![1]).await.unwrap_err();
+    assert!(matches!(err, Error::Server(msg) if msg == "out of memory"));
+    let err = client.get("a").await.unwrap_err();
+    assert!(matches!(err, Error::UnexpectedResponse(Response::Pong)));
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use super::connect;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_batch() -> anyhow::Result<()> {
+    let mut client = connect().await;
+
+    let items = [
+        ("a", vec![1], None),
+        ("b", vec![2], Some(Duration::from_secs(60))),
+        ("c", vec![3], None),
+    ];
+    client.set_many(items).await?;
+
+    let values = client.get_many(["a", "missing", "c", "b"]).await?;
+    assert_eq!(
+        values,
+        vec![Some(vec![1]), None, Some(vec![3]), Some(vec![2])]
+    );
+
+    let deleted = client.delete_many(["a", "missing"]).await?;
+    assert_eq!(deleted, vec![true, false]);
+    assert_eq!(client.get("a").await?, None);
+
+    assert_eq!(client.get_many(Vec::<Vec<u8>>::new()).await?, vec![]);
+    Ok(())
+}
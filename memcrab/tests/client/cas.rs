@@ -0,0 +1,52 @@
+use super::connect;
+use memcrab::CasOutcome;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_cas() -> anyhow::Result<()> {
+    let mut client = connect().await;
+
+    assert_eq!(client.get_with_version("counter").await?, None);
+    let outcome = client.compare_and_swap("counter", vec![0], None, 0).await?;
+    assert_eq!(outcome, CasOutcome::NotFound);
+
+    client.set("counter", vec![1]).await?;
+    let (value, version) = client.get_with_version("counter").await?.unwrap();
+    assert_eq!(value, vec![1]);
+
+    let outcome = client
+        .compare_and_swap("counter", vec![2], None, version)
+        .await?;
+    assert_eq!(outcome, CasOutcome::Stored);
+    let outcome = client
+        .compare_and_swap("counter", vec![3], None, version)
+        .await?;
+    assert_eq!(outcome, CasOutcome::VersionMismatch);
+    assert_eq!(client.get("counter").await?, Some(vec![2]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cas_ttl() -> anyhow::Result<()> {
+    let mut client = connect().await;
+    let minute = Duration::from_secs(60);
+
+    client
+        .set_with_expiration("session", vec![1], minute)
+        .await?;
+    let (_, version) = client.get_with_version("session").await?.unwrap();
+    let outcome = client
+        .compare_and_swap("session", vec![2], Some(minute), version)
+        .await?;
+    assert_eq!(outcome, CasOutcome::Stored);
+    let ttl = client.ttl("session").await?.flatten().unwrap();
+    assert!(ttl <= minute && ttl > minute - Duration::from_secs(1));
+
+    let (_, version) = client.get_with_version("session").await?.unwrap();
+    let outcome = client
+        .compare_and_swap("session", vec![3], None, version)
+        .await?;
+    assert_eq!(outcome, CasOutcome::Stored);
+    assert_eq!(client.ttl("session").await?, Some(None));
+    Ok(())
+}
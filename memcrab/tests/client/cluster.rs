@@ -0,0 +1,143 @@
+use super::{start_server, start_server_at, start_server_with};
+use memcrab::{connections::Tcp, Cluster, Error, RawClient};
+use memcrab_server::ServerCfg;
+use std::time::Duration;
+
+fn keys() -> Vec<String> {
+    (0..100).map(|i| format!("key-{i}")).collect()
+}
+
+#[tokio::test]
+async fn test_cluster() -> anyhow::Result<()> {
+    let mut addrs = vec![];
+    for _ in 0..3 {
+        addrs.push(start_server().await);
+    }
+    let cluster = Cluster::builder()
+        .node(addrs[0], 1)
+        .node(addrs[1], 1)
+        .node(addrs[2], 2)
+        .build();
+
+    let items = keys()
+        .into_iter()
+        .enumerate()
+        .map(|(i, key)| (key, vec![i as u8], None));
+    cluster.set_many(items).await?;
+    let mut stored = 0;
+    for addr in &addrs {
+        let mut node = RawClient::<Tcp>::connect(*addr).await?;
+        let items = node.stats().await?.total().items;
+        assert!(items > 0);
+        stored += items;
+    }
+    assert_eq!(stored, 100);
+
+    let values = cluster.get_many(keys()).await?;
+    let expected: Vec<_> = (0..100).map(|i| Some(vec![i as u8])).collect();
+    assert_eq!(values, expected);
+    assert_eq!(cluster.get("key-7").await?, Some(vec![7]));
+    assert_eq!(cluster.increment_or("counter", 2, 2, None).await?, 2);
+    assert_eq!(cluster.increment("counter", 3).await?, Some(5));
+
+    let deleted = cluster.delete_many(["key-1", "missing", "key-2"]).await?;
+    assert_eq!(deleted, [true, false, true]);
+    assert!(cluster.delete("key-3").await?);
+    assert_eq!(cluster.get("key-3").await?, None);
+
+    cluster.clear().await?;
+    assert!(cluster.get_many(keys()).await?.iter().all(Option::is_none));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cluster_ejection() -> anyhow::Result<()> {
+    let (healthy, _stop, _server) = start_server_with(ServerCfg::default()).await;
+    let (flaky, stop, server) = start_server_with(ServerCfg::default()).await;
+    let cluster = Cluster::builder()
+        .node(healthy, 1)
+        .node(flaky, 1)
+        .failure_limit(1)
+        .retry_interval(Duration::from_millis(300))
+        .build();
+    cluster
+        .set_many(keys().into_iter().map(|key| (key, vec![1], None)))
+        .await?;
+    // the keys that live on the flaky node
+    let mut node = RawClient::<Tcp>::connect(flaky).await?;
+    let values = node.get_many(keys()).await?;
+    let key = keys()
+        .into_iter()
+        .zip(values)
+        .find_map(|(key, value)| value.map(|_| key))
+        .unwrap();
+    drop(node);
+
+    stop.send(()).unwrap();
+    server.await?;
+    // the failure ejects the node, then its keys go to the other one
+    assert!(matches!(
+        cluster.get(key.as_str()).await,
+        Err(Error::Protocol(_))
+    ));
+    assert_eq!(cluster.get(key.as_str()).await?, None);
+    cluster.set(key.as_str(), vec![2]).await?;
+    assert_eq!(cluster.get(key.as_str()).await?, Some(vec![2]));
+
+    // tried again after the interval, the restarted node is empty
+    let (_, _stop, _server) = start_server_at(flaky, ServerCfg::default()).await;
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(cluster.get(key.as_str()).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cluster_node_down_in_batch() -> anyhow::Result<()> {
+    let (first, _first_stop, _first) = start_server_with(ServerCfg::default()).await;
+    let (second, _second_stop, _second) = start_server_with(ServerCfg::default()).await;
+    let (down, stop, server) = start_server_with(ServerCfg::default()).await;
+    let cluster = Cluster::builder()
+        .node(first, 1)
+        .node(second, 1)
+        .node(down, 1)
+        .failure_limit(1)
+        .build();
+    cluster
+        .set_many(keys().into_iter().map(|key| (key, vec![1], None)))
+        .await?;
+    let mut node = RawClient::<Tcp>::connect(down).await?;
+    let lost = node.get_many(keys()).await?;
+    drop(node);
+    stop.send(()).unwrap();
+    server.await?;
+
+    // the node is ejected, its keys are asked from the others, which don't have them
+    let values = cluster.get_many(keys()).await?;
+    for (value, lost) in values.into_iter().zip(lost) {
+        assert_eq!(value.is_none(), lost.is_some());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cluster_without_nodes() -> anyhow::Result<()> {
+    let cluster = Cluster::builder().build();
+    assert!(matches!(
+        cluster.get("key").await,
+        Err(Error::NoNodeAvailable)
+    ));
+    assert_eq!(cluster.get_many(Vec::<String>::new()).await?, vec![]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cluster_heavy_node() -> anyhow::Result<()> {
+    // the weight is clamped, the ring stays small
+    let cluster = Cluster::builder()
+        .node(start_server().await, u32::MAX)
+        .node(start_server().await, 1)
+        .build();
+    cluster.set("key", vec![1]).await?;
+    assert_eq!(cluster.get("key").await?, Some(vec![1]));
+    Ok(())
+}
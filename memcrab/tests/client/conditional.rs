@@ -0,0 +1,29 @@
+use super::connect;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_conditional_set() -> anyhow::Result<()> {
+    let mut client = connect().await;
+    let ttl = Some(Duration::from_secs(30));
+
+    assert!(!client.replace("lock", vec![0], None).await?);
+    assert!(client.add("lock", vec![1], ttl).await?);
+    assert!(!client.add("lock", vec![2], ttl).await?);
+    assert_eq!(client.get("lock").await?, Some(vec![1]));
+
+    assert!(client.replace("lock", vec![3], None).await?);
+    assert_eq!(client.get("lock").await?, Some(vec![3]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_append() -> anyhow::Result<()> {
+    let mut client = connect().await;
+
+    assert!(!client.append("events", vec![1]).await?);
+    client.set("events", vec![2]).await?;
+    assert!(client.append("events", vec![3, 4]).await?);
+    assert!(client.prepend("events", vec![1]).await?);
+    assert_eq!(client.get("events").await?, Some(vec![1, 2, 3, 4]));
+    Ok(())
+}
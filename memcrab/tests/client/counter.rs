@@ -0,0 +1,20 @@
+use super::connect;
+use memcrab::Error;
+
+#[tokio::test]
+async fn test_counter() -> anyhow::Result<()> {
+    let mut client = connect().await;
+
+    assert_eq!(client.increment("requests", 1).await?, None);
+    assert_eq!(client.increment_or("requests", 1, 1, None).await?, 1);
+    assert_eq!(client.increment_or("requests", 1, 1, None).await?, 2);
+    assert_eq!(client.increment("requests", 40).await?, Some(42));
+    assert_eq!(client.decrement("requests", 2).await?, Some(40));
+    assert_eq!(client.decrement_or("requests", 100, 0, None).await?, 0);
+    assert_eq!(client.get("requests").await?, Some(b"0".to_vec()));
+
+    client.set("name", b"crab".to_vec()).await?;
+    let err = client.increment("name", 1).await.unwrap_err();
+    assert!(matches!(err, Error::NotCounter));
+    Ok(())
+}
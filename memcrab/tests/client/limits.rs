@@ -0,0 +1,40 @@
+use super::{start_server, start_server_with};
+use memcrab::{
+    connections::{ConnectionCfg, Tcp},
+    Error, RawClient,
+};
+use memcrab_protocol::{Error as ProtocolError, ParseError};
+use memcrab_server::ServerCfg;
+
+#[tokio::test]
+async fn test_server_payload_limit() -> anyhow::Result<()> {
+    let cfg = ServerCfg::builder().max_payload_len(64).build();
+    let (addr, _stop, _server) = start_server_with(cfg).await;
+
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    client.set("a", vec![0; 32]).await?;
+
+    let err = client.set("a", vec![0; 64]).await.unwrap_err();
+    assert!(matches!(err, Error::Server(msg) if msg.contains("64 bytes")));
+    // the server closes the connection after refusing a frame
+    assert!(client.ping().await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_payload_limit() -> anyhow::Result<()> {
+    let addr = start_server().await;
+    let cfg = ConnectionCfg::default().max_payload_len(16);
+    let mut client = RawClient::<Tcp>::connect_with_cfg(addr, &cfg).await?;
+
+    client.set("a", vec![0; 16]).await?;
+    assert_eq!(client.get("a").await?, Some(vec![0; 16]));
+
+    client.set("b", vec![0; 17]).await?;
+    let err = client.get("b").await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::Parse(ParseError::TooBig { len: 17, .. }))
+    ));
+    Ok(())
+}
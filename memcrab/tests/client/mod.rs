@@ -0,0 +1,59 @@
+mod basic;
+mod batch;
+mod cas;
+mod cluster;
+mod conditional;
+mod counter;
+mod limits;
+mod multiplexed;
+mod oplog;
+mod pool;
+mod reconnect;
+mod replication;
+mod snapshot;
+mod stats;
+mod ttl;
+
+use memcrab::{connections::Tcp, RawClient};
+use memcrab_server::{serve, serve_with_shutdown, Cache, ServerCfg, ServerHandle};
+use std::net::SocketAddr;
+use tokio::{net::TcpListener, sync::oneshot};
+
+fn cache() -> Cache {
+    Cache::builder()
+        .segments(4)
+        .max_bytesize(2_usize.pow(20))
+        .build()
+        .into()
+}
+
+pub async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, cache()));
+    addr
+}
+
+/// A server with `cfg`, it stops when `stop` is sent or dropped.
+pub async fn start_server_with(cfg: ServerCfg) -> (SocketAddr, oneshot::Sender<()>, ServerHandle) {
+    start_server_at("127.0.0.1:0".parse().unwrap(), cfg).await
+}
+
+/// Like [`start_server_with`] on `addr`, e.g. to restart a server where it was.
+pub async fn start_server_at(
+    addr: SocketAddr,
+    cfg: ServerCfg,
+) -> (SocketAddr, oneshot::Sender<()>, ServerHandle) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, signal) = oneshot::channel();
+    let server = serve_with_shutdown(listener, cache(), cfg, async {
+        let _ = signal.await;
+    });
+    (addr, stop, server)
+}
+
+pub async fn connect() -> RawClient<Tcp> {
+    let addr = start_server().await;
+    RawClient::<Tcp>::connect(addr).await.unwrap()
+}
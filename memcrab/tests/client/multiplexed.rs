@@ -0,0 +1,25 @@
+use super::start_server;
+use memcrab::{connections::Multiplexed, RawClient};
+
+#[tokio::test]
+async fn test_shared_connection() -> anyhow::Result<()> {
+    let addr = start_server().await;
+    let client = RawClient::<Multiplexed>::connect_tcp(addr).await?;
+
+    let tasks = (0..50u32).map(|i| {
+        let mut client = client.clone();
+        tokio::spawn(async move {
+            let key = format!("key-{i}");
+            let value = i.to_be_bytes().to_vec();
+            for _ in 0..10 {
+                client.set(key.as_str(), value.clone()).await?;
+                assert_eq!(client.get(key.as_str()).await?, Some(value.clone()));
+            }
+            anyhow::Ok(())
+        })
+    });
+    for task in tasks.collect::<Vec<_>>() {
+        task.await??;
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use super::start_server_with;
+use memcrab::{connections::Tcp, RawClient};
+use memcrab_server::{Fsync, ServerCfg};
+use std::path::Path;
+
+fn cfg(path: &Path) -> ServerCfg {
+    ServerCfg::builder()
+        .oplog_path(path.to_owned())
+        .oplog_fsync(Fsync::Always)
+        .build()
+}
+
+#[tokio::test]
+async fn test_oplog() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("memcrab-test-{}.oplog", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let (addr, stop, server) = start_server_with(cfg(&path)).await;
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    client.set("a", b"1".to_vec()).await?;
+    client.set("b", vec![2]).await?;
+    assert!(client.append("b", vec![3]).await?);
+    assert_eq!(client.increment("a", 41).await?, Some(42));
+    assert!(client.add("c", vec![4], None).await?);
+    assert!(!client.add("c", vec![5], None).await?);
+    client.set("d", vec![6]).await?;
+    assert!(client.delete("d").await?);
+    drop(client);
+
+    // concurrent changes of one key are logged in their order
+    let mut tasks = tokio::task::JoinSet::new();
+    for i in 0..8_u8 {
+        tasks.spawn(async move {
+            let mut client = RawClient::<Tcp>::connect(addr).await?;
+            for _ in 0..50 {
+                client.increment_or("n", 1, 1, None).await?;
+                client.set(format!("key-{i}"), vec![i]).await?;
+            }
+            anyhow::Ok(())
+        });
+    }
+    while let Some(task) = tasks.join_next().await {
+        task??;
+    }
+    stop.send(()).unwrap();
+    server.await?;
+
+    let (addr, stop, server) = start_server_with(cfg(&path)).await;
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    assert_eq!(client.get("a").await?, Some(b"42".to_vec()));
+    assert_eq!(client.get("b").await?, Some(vec![2, 3]));
+    assert_eq!(client.get("c").await?, Some(vec![4]));
+    assert_eq!(client.get("d").await?, None);
+    assert_eq!(client.get("n").await?, Some(b"400".to_vec()));
+    for i in 0..8_u8 {
+        assert_eq!(client.get(format!("key-{i}")).await?, Some(vec![i]));
+    }
+    drop(client);
+    stop.send(()).unwrap();
+    server.await?;
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
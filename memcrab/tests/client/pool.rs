@@ -0,0 +1,88 @@
+use super::{start_server, start_server_at, start_server_with};
+use memcrab::{connections::Tcp, Error, Pool, RawClient};
+use memcrab_server::ServerCfg;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_pool() -> anyhow::Result<()> {
+    let addr = start_server().await;
+    let pool = Pool::builder(addr).min_size(1).max_size(3).build().await?;
+    assert_eq!(pool.size(), 1);
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for i in 0..30_u8 {
+        let pool = pool.clone();
+        tasks.spawn(async move {
+            pool.set(format!("key-{i}"), vec![i]).await?;
+            pool.get(format!("key-{i}")).await
+        });
+    }
+    let mut values = vec![];
+    while let Some(value) = tasks.join_next().await {
+        values.push(value??.unwrap()[0]);
+    }
+    values.sort();
+    assert_eq!(values, (0..30).collect::<Vec<_>>());
+    assert!(pool.size() <= 3);
+
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    assert!(client.stats().await?.total_connections <= 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pool_checkout_timeout() -> anyhow::Result<()> {
+    let addr = start_server().await;
+    let pool = Pool::builder(addr)
+        .max_size(1)
+        .checkout_timeout(Duration::from_millis(50))
+        .build()
+        .await?;
+
+    let mut client = pool.checkout().await?;
+    client.set("key", vec![1]).await?;
+    assert!(matches!(pool.get("key").await, Err(Error::CheckoutTimeout)));
+    drop(client);
+    assert_eq!(pool.get("key").await?, Some(vec![1]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pool_discards_broken_connections() -> anyhow::Result<()> {
+    let (addr, stop, server) = start_server_with(ServerCfg::default()).await;
+    let pool = Pool::builder(addr).min_size(2).build().await?;
+    pool.ping().await?;
+    stop.send(()).unwrap();
+    server.await?;
+    let (_, _stop, _server) = start_server_at(addr, ServerCfg::default()).await;
+
+    // the connection to the old server fails once and is closed
+    assert!(matches!(pool.ping().await, Err(Error::Protocol(_))));
+    assert_eq!(pool.size(), 1);
+    pool.ping().await.unwrap_err();
+    assert_eq!(pool.size(), 0);
+    pool.ping().await?;
+    assert_eq!(pool.size(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pool_health_checks() -> anyhow::Result<()> {
+    let (addr, stop, server) = start_server_with(ServerCfg::default()).await;
+    let pool = Pool::builder(addr)
+        .min_size(2)
+        .health_check_interval(Duration::from_millis(100))
+        .build()
+        .await?;
+    stop.send(()).unwrap();
+    server.await?;
+    let (_, _stop, _server) = start_server_at(addr, ServerCfg::default()).await;
+
+    // broken connections are replaced before they are used
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(pool.size(), 2);
+    let (mut first, mut second) = (pool.checkout().await?, pool.checkout().await?);
+    first.ping().await?;
+    second.ping().await?;
+    Ok(())
+}
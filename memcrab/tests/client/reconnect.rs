@@ -0,0 +1,86 @@
+use super::{start_server_at, start_server_with};
+use memcrab::{
+    connections::{ConnectionCfg, Reconnecting, RetryPolicy},
+    Error, RawClient,
+};
+use memcrab_protocol::{Error as ProtocolError, ParseError};
+use memcrab_server::ServerCfg;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn test_reconnect() -> anyhow::Result<()> {
+    let (addr, stop, server) = start_server_with(ServerCfg::default()).await;
+    let mut client = RawClient::<Reconnecting>::connect(addr).await?;
+    client.set("key", vec![1]).await?;
+    stop.send(()).unwrap();
+    server.await?;
+    let (_, stop, server) = start_server_at(addr, ServerCfg::default()).await;
+
+    // idempotent requests are retried on a new connection
+    assert_eq!(client.get("key").await?, None);
+    client.set("key", vec![2]).await?;
+    assert_eq!(client.get("key").await?, Some(vec![2]));
+    stop.send(()).unwrap();
+    server.await?;
+    let (_, _stop, _server) = start_server_at(addr, ServerCfg::default()).await;
+
+    // others fail once, the next request reconnects
+    assert!(matches!(
+        client.set("key", vec![3]).await,
+        Err(Error::Protocol(_))
+    ));
+    client.set("key", vec![3]).await?;
+    assert_eq!(client.get("key").await?, Some(vec![3]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retries_exhausted() -> anyhow::Result<()> {
+    let (addr, stop, server) = start_server_with(ServerCfg::default()).await;
+    let policy = RetryPolicy::default()
+        .max_retries(2)
+        .initial_backoff(Duration::from_millis(20))
+        .max_backoff(Duration::from_millis(30));
+    let mut client =
+        RawClient::<Reconnecting>::connect_with_cfg(addr, &ConnectionCfg::default(), policy)
+            .await?;
+    stop.send(()).unwrap();
+    server.await?;
+
+    let started = Instant::now();
+    match client.ping().await {
+        Err(Error::RetriesExhausted { attempts, last }) => {
+            assert_eq!(attempts, 3);
+            assert!(matches!(*last, Error::Protocol(_)));
+        }
+        result => panic!("unexpected result: {result:?}"),
+    }
+    assert!(started.elapsed() >= Duration::from_millis(50));
+
+    // connecting is retried for any request, since nothing has been sent
+    assert!(matches!(
+        client.set("key", vec![1]).await,
+        Err(Error::RetriesExhausted { attempts: 3, .. })
+    ));
+    let (_, _stop, _server) = start_server_at(addr, ServerCfg::default()).await;
+    client.set("key", vec![1]).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_retry_of_parse_errors() -> anyhow::Result<()> {
+    let (addr, _stop, _server) = start_server_with(ServerCfg::default()).await;
+    let cfg = ConnectionCfg::default().max_payload_len(16);
+    let mut client =
+        RawClient::<Reconnecting>::connect_with_cfg(addr, &cfg, RetryPolicy::default()).await?;
+    client.set("big", vec![0; 17]).await?;
+
+    // the response would be as big on a new connection
+    let err = client.get("big").await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::Parse(ParseError::TooBig { .. }))
+    ));
+    assert_eq!(client.get("missing").await?, None);
+    Ok(())
+}
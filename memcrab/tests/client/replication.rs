@@ -0,0 +1,107 @@
+use super::{start_server, start_server_with};
+use memcrab::{connections::Tcp, Error, RawClient};
+use memcrab_server::ServerCfg;
+use std::{net::SocketAddr, time::Duration};
+
+fn cfg(replica_of: Option<SocketAddr>) -> ServerCfg {
+    ServerCfg::builder()
+        .replica_of(replica_of)
+        .allow_replication(true)
+        .build()
+}
+
+// Replication is asynchronous, so wait for a change to arrive.
+async fn eventually_get(
+    client: &mut RawClient<Tcp>,
+    key: &str,
+    expected: Option<Vec<u8>>,
+) -> anyhow::Result<()> {
+    for _ in 0..200 {
+        if client.get(key).await? == expected {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    anyhow::bail!("{key} is not replicated")
+}
+
+#[tokio::test]
+async fn test_replication() -> anyhow::Result<()> {
+    let (primary_addr, _primary_stop, _primary) = start_server_with(cfg(None)).await;
+    let mut primary = RawClient::<Tcp>::connect(primary_addr).await?;
+    primary.set("old", vec![1]).await?;
+    primary
+        .set_with_expiration("expiring", vec![2], Duration::from_secs(60))
+        .await?;
+
+    let (replica_addr, _replica_stop, _replica) = start_server_with(cfg(Some(primary_addr))).await;
+    let mut replica = RawClient::<Tcp>::connect(replica_addr).await?;
+    eventually_get(&mut replica, "old", Some(vec![1])).await?;
+    assert_eq!(replica.get("expiring").await?, Some(vec![2]));
+    assert!(
+        matches!(replica.ttl("expiring").await?, Some(Some(ttl)) if ttl > Duration::from_secs(50))
+    );
+
+    primary.set("new", vec![3]).await?;
+    primary.delete("old").await?;
+    primary.increment_or("counter", 5, 5, None).await?;
+    eventually_get(&mut replica, "new", Some(vec![3])).await?;
+    eventually_get(&mut replica, "old", None).await?;
+    eventually_get(&mut replica, "counter", Some(b"5".to_vec())).await?;
+
+    let err = replica.set("new", vec![4]).await.unwrap_err();
+    assert!(matches!(err, Error::Server(msg) if msg.contains("read-only")));
+    assert_eq!(replica.get("new").await?, Some(vec![3]));
+
+    replica.promote().await?;
+    replica.set("new", vec![4]).await?;
+    assert_eq!(replica.get("new").await?, Some(vec![4]));
+    // no longer follows the primary
+    primary.set("after", vec![5]).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(replica.get("after").await?, None);
+    // promoting a primary changes nothing
+    primary.promote().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replication_not_allowed() -> anyhow::Result<()> {
+    let primary_addr = start_server().await;
+    let mut primary = RawClient::<Tcp>::connect(primary_addr).await?;
+    primary.set("key", vec![1]).await?;
+    let err = primary.promote().await.unwrap_err();
+    assert!(matches!(err, Error::Server(msg) if msg.contains("not allowed")));
+
+    let (replica_addr, _replica_stop, _replica) = start_server_with(cfg(Some(primary_addr))).await;
+    let mut replica = RawClient::<Tcp>::connect(replica_addr).await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(replica.get("key").await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replication_of_big_values() -> anyhow::Result<()> {
+    // a value grows past the payload limit of both servers
+    let limited = |replica_of| {
+        ServerCfg::builder()
+            .replica_of(replica_of)
+            .allow_replication(true)
+            .max_payload_len(1024)
+            .build()
+    };
+    let (primary_addr, _primary_stop, _primary) = start_server_with(limited(None)).await;
+    let (replica_addr, _replica_stop, _replica) =
+        start_server_with(limited(Some(primary_addr))).await;
+    let mut primary = RawClient::<Tcp>::connect(primary_addr).await?;
+    let mut replica = RawClient::<Tcp>::connect(replica_addr).await?;
+    primary.set("big", vec![]).await?;
+    eventually_get(&mut replica, "big", Some(vec![])).await?;
+
+    for i in 0..200 {
+        assert!(primary.append("big", vec![i as u8; 900]).await?);
+    }
+    let expected: Vec<_> = (0..200).flat_map(|i| vec![i as u8; 900]).collect();
+    eventually_get(&mut replica, "big", Some(expected)).await?;
+    Ok(())
+}
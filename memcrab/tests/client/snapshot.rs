@@ -0,0 +1,47 @@
+use super::start_server_with;
+use memcrab::{connections::Tcp, Error, RawClient};
+use memcrab_server::ServerCfg;
+use std::{path::Path, time::Duration};
+
+fn cfg(path: &Path) -> ServerCfg {
+    ServerCfg::builder().snapshot_path(path.to_owned()).build()
+}
+
+#[tokio::test]
+async fn test_snapshot() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("memcrab-test-{}.snapshot", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let (addr, stop, server) = start_server_with(cfg(&path)).await;
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    client.set("a", vec![1]).await?;
+    client.save().await?;
+    assert!(path.exists());
+    client
+        .set_with_expiration("b", vec![2], Duration::from_secs(60))
+        .await?;
+    drop(client);
+    // the rest is saved on shutdown
+    stop.send(()).unwrap();
+    server.await?;
+
+    let (addr, stop, server) = start_server_with(cfg(&path)).await;
+    let mut client = RawClient::<Tcp>::connect(addr).await?;
+    assert_eq!(client.get("a").await?, Some(vec![1]));
+    assert_eq!(client.get("b").await?, Some(vec![2]));
+    assert!(matches!(client.ttl("b").await?, Some(Some(ttl)) if ttl > Duration::from_secs(50)));
+    drop(client);
+    stop.send(()).unwrap();
+    server.await?;
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_save_without_path() -> anyhow::Result<()> {
+    let mut client = super::connect().await;
+    let err = client.save().await.unwrap_err();
+    assert!(matches!(err, Error::Server(msg) if msg.contains("not configured")));
+    Ok(())
+}
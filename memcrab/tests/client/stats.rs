@@ -0,0 +1,31 @@
+use super::connect;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_stats() -> anyhow::Result<()> {
+    let mut client = connect().await;
+
+    client.set("a", vec![1, 2]).await?;
+    client
+        .set_with_expiration("b", vec![3], Duration::ZERO)
+        .await?;
+    assert_eq!(client.get("a").await?, Some(vec![1, 2]));
+    assert_eq!(client.get("b").await?, None);
+    assert_eq!(client.get("c").await?, None);
+    assert!(client.delete("a").await?);
+    client.set("d", vec![4]).await?;
+
+    let stats = client.stats().await?;
+    assert_eq!(stats.segments.len(), 4);
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.total_connections, 1);
+    assert!(stats.uptime < Duration::from_secs(60));
+
+    let total = stats.total();
+    assert_eq!((total.hits, total.misses), (1, 2));
+    assert_eq!((total.sets, total.deletes, total.expirations), (3, 1, 1));
+    assert_eq!(total.items, 1);
+    assert_eq!(total.bytesize, 2);
+    assert_eq!(total.max_bytesize, 2_u64.pow(20));
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use super::connect;
+use std::time::{Duration, SystemTime};
+
+#[tokio::test]
+async fn test_ttl() -> anyhow::Result<()> {
+    let mut client = connect().await;
+    let minute = Duration::from_secs(60);
+
+    assert_eq!(client.ttl("session").await?, None);
+    assert!(!client.touch("session", minute).await?);
+    assert_eq!(client.get_and_touch("session", minute).await?, None);
+
+    client.set("session", vec![1]).await?;
+    assert_eq!(client.ttl("session").await?, Some(None));
+
+    assert!(client.touch("session", minute).await?);
+    let ttl = client.ttl("session").await?.flatten().unwrap();
+    assert!(ttl <= minute && ttl > minute - Duration::from_secs(1));
+
+    assert!(client.persist("session").await?);
+    assert_eq!(client.ttl("session").await?, Some(None));
+
+    let value = client.get_and_touch("session", minute).await?;
+    assert_eq!(value, Some(vec![1]));
+    assert!(client.ttl("session").await?.flatten().is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_precise_expiration() -> anyhow::Result<()> {
+    let mut client = connect().await;
+    let ttl = Duration::from_millis(100);
+
+    client
+        .set_many([("token", vec![1], Some(ttl)), ("session", vec![2], None)])
+        .await?;
+    let deadline = SystemTime::now() + ttl;
+    client.set_with_deadline("nonce", vec![3], deadline).await?;
+    assert!(client.ttl("token").await?.flatten().unwrap() <= ttl);
+    assert!(client.ttl("nonce").await?.flatten().unwrap() <= ttl);
+
+    tokio::time::sleep(ttl).await;
+    let values = client.get_many(["token", "session", "nonce"]).await?;
+    assert_eq!(values, [None, Some(vec![2]), None]);
+
+    let past = SystemTime::now() - Duration::from_secs(1);
+    client.set_with_deadline("nonce", vec![3], past).await?;
+    assert_eq!(client.get("nonce").await?, None);
+    Ok(())
+}
@@ -1 +1,2 @@
+mod client;
 mod readme;
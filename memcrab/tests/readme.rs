@@ -1,4 +1,8 @@
-use memcrab::{connections::Tcp, Error, RawClient};
+use memcrab::{
+    connections::{ConnectionCfg, Multiplexed, Reconnecting, RetryPolicy, Tcp},
+    Cluster, Error, Pool, RawClient,
+};
+use std::time::Duration;
 
 #[allow(dead_code)]
 async fn tcp_raw_client_readme() -> Result<(), Error> {
@@ -13,3 +17,61 @@ async fn tcp_raw_client_readme() -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[allow(dead_code)]
+async fn multiplexed_raw_client_readme() -> Result<(), Error> {
+    let addr = "127.0.0.1:80".parse().unwrap();
+    let client = RawClient::<Multiplexed>::connect_tcp(addr).await?;
+
+    let mut handle = client.clone();
+    tokio::spawn(async move { handle.set("date", vec![2, 3, 24]).await });
+    let name = client.clone().get("name").await?;
+    println!("{:?}", name);
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn pool_readme() -> Result<(), Error> {
+    let addr: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let pool = Pool::builder(addr)
+        .min_size(2)
+        .max_size(16)
+        .checkout_timeout(Duration::from_secs(1))
+        .build()
+        .await?;
+
+    let handle = pool.clone();
+    tokio::spawn(async move { handle.set("date", vec![2, 3, 24]).await });
+    let name = pool.get("name").await?;
+    println!("{:?}", name);
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn reconnecting_readme() -> Result<(), Error> {
+    let addr: std::net::SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let policy = RetryPolicy::default()
+        .max_retries(5)
+        .max_backoff(Duration::from_secs(1));
+    let mut client =
+        RawClient::<Reconnecting>::connect_with_cfg(addr, &ConnectionCfg::default(), policy)
+            .await?;
+
+    client.set("date", vec![2, 3, 24]).await?;
+    let name = client.get("name").await?;
+    println!("{:?}", name);
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn cluster_readme() -> Result<(), Error> {
+    let cluster = Cluster::builder()
+        .node("10.0.0.1:9090".parse::<std::net::SocketAddr>().unwrap(), 1)
+        .node("10.0.0.2:9090".parse::<std::net::SocketAddr>().unwrap(), 2)
+        .build();
+
+    cluster.set("date", vec![2, 3, 24]).await?;
+    let values = cluster.get_many(["date", "name"]).await?;
+    println!("{:?}", values);
+    Ok(())
+}